@@ -0,0 +1,158 @@
+use crate::colors::is_light;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+/// How long to wait for the terminal to answer the OSC 11 query.
+const QUERY_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Environment variables identifying a terminal window or pane, most specific
+/// first.
+const SESSION_VARS: [&str; 7] = [
+    "TMUX_PANE",
+    "KITTY_WINDOW_ID",
+    "WEZTERM_PANE",
+    "ITERM_SESSION_ID",
+    "TERM_SESSION_ID",
+    "WT_SESSION",
+    "ALACRITTY_WINDOW_ID",
+];
+
+/// Guesses whether the terminal background is light for `--background auto`:
+/// from `COLORFGBG`, else, when `query` is set, from the answer to an OSC 11
+/// query, cached per terminal session.
+pub fn detect_light_background(query: bool) -> Option<bool> {
+    if let Some(light) = std::env::var("COLORFGBG")
+        .ok()
+        .and_then(|v| parse_colorfgbg(&v))
+    {
+        return Some(light);
+    }
+    if !query || std::env::var("TERM").is_ok_and(|t| t == "dumb") {
+        return None;
+    }
+    // The query briefly takes over the terminal from the program foxtail runs
+    // under, so it must not be repeated on every render.
+    let session = terminal_session()?;
+    if let Some(light) = crate::cache::load_background(&session) {
+        return Some(light);
+    }
+    let light = is_light(query_background()?);
+    crate::cache::store_background(&session, light);
+    Some(light)
+}
+
+/// Reads the background from `COLORFGBG` ("fg;bg" or "fg;default;bg"), as set
+/// by rxvt, Konsole and others. Like rxvt, palette entries 7 and 9-15 count as
+/// light.
+pub fn parse_colorfgbg(value: &str) -> Option<bool> {
+    let bg: u8 = value.rsplit(';').next()?.trim().parse().ok()?;
+    match bg {
+        0..=6 | 8 => Some(false),
+        7 | 9..=15 => Some(true),
+        _ => None,
+    }
+}
+
+/// Extracts the color from an OSC 11 reply such as
+/// `ESC ] 11 ; rgb:ffff/ffff/dddd BEL`. Each channel has 1 to 4 hex digits.
+pub fn parse_osc11_response(response: &str) -> Option<(u8, u8, u8)> {
+    let start = response.find("]11;rgb:")? + "]11;rgb:".len();
+    let rest = &response[start..];
+    let end = rest.find(['\x07', '\x1b']).unwrap_or(rest.len());
+    let channels: Vec<u8> = rest[..end]
+        .split('/')
+        .map(|c| {
+            if c.is_empty() || c.len() > 4 {
+                return None;
+            }
+            let value = u32::from_str_radix(c, 16).ok()?;
+            let max = (1u32 << (4 * c.len())) - 1;
+            Some((value * 255 / max) as u8)
+        })
+        .collect::<Option<_>>()?;
+    match channels[..] {
+        [r, g, b] => Some((r, g, b)),
+        _ => None,
+    }
+}
+
+/// Identifies the terminal session: a terminal-specific variable, else the
+/// Unix session id together with its leader's start time so that recycled ids
+/// do not reuse a stale answer.
+fn terminal_session() -> Option<String> {
+    for var in SESSION_VARS {
+        if let Some(value) = std::env::var(var).ok().filter(|v| !v.is_empty()) {
+            return Some(format!("{}={}", var, value));
+        }
+    }
+    let own = fs::read_to_string("/proc/self/stat").ok()?;
+    let sid = stat_field(&own, 6)?;
+    let leader = fs::read_to_string(format!("/proc/{}/stat", sid)).ok()?;
+    Some(format!("sid={}@{}", sid, stat_field(&leader, 22)?))
+}
+
+/// Returns field `n` (1-based, as in proc(5)) of a `/proc/<pid>/stat` line.
+pub fn stat_field(stat: &str, n: usize) -> Option<&str> {
+    // The command name (field 2) is parenthesized and may contain spaces.
+    let rest = &stat[stat.rfind(')')? + 1..];
+    rest.split_whitespace().nth(n.checked_sub(3)?)
+}
+
+fn stty(tty: &File, args: &[&str]) -> Option<String> {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::from(tty.try_clone().ok()?))
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Asks the controlling terminal for its background color. A primary device
+/// attributes request follows, which every terminal answers, so terminals
+/// without OSC 11 support do not cost the whole timeout.
+fn query_background() -> Option<(u8, u8, u8)> {
+    let mut tty = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/tty")
+        .ok()?;
+    let saved = stty(&tty, &["-g"])?;
+    // `min 0 time 1` makes reads give up after 0.1s, so nothing is left
+    // reading the user's keystrokes once the query is over.
+    stty(&tty, &["raw", "-echo", "min", "0", "time", "1"])?;
+    let response = tty
+        .write_all(b"\x1b]11;?\x07\x1b[c")
+        .and_then(|_| tty.flush())
+        .ok()
+        .map(|_| read_reply(&mut tty));
+    stty(&tty, &[&saved]);
+    parse_osc11_response(&response?)
+}
+
+/// Reads the terminal's answers until the device attributes reply
+/// (`ESC [ ? ... c`) ends the exchange or `QUERY_TIMEOUT` has passed.
+fn read_reply(tty: &mut File) -> String {
+    let deadline = Instant::now() + QUERY_TIMEOUT;
+    let mut response = Vec::new();
+    let mut buf = [0u8; 64];
+    while Instant::now() < deadline {
+        match tty.read(&mut buf) {
+            Ok(n) => response.extend_from_slice(&buf[..n]),
+            Err(_) => break,
+        }
+        let text = String::from_utf8_lossy(&response);
+        if text
+            .rfind("\x1b[?")
+            .is_some_and(|i| text[i..].contains('c'))
+        {
+            break;
+        }
+    }
+    String::from_utf8_lossy(&response).to_string()
+}
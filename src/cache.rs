@@ -0,0 +1,195 @@
+use crate::git::{find_repo, Vcs, VcsInfo};
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::fs::{self, OpenOptions};
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Identifies the state of a repository cheaply: its root, plus the mtimes of
+/// the files every VCS rewrites when the index, working copy parent or current
+/// branch changes.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CacheKey {
+    pub root: PathBuf,
+    pub stamps: Vec<Option<u128>>,
+    /// Whether untracked files were scanned, which changes the result.
+    pub untracked: bool,
+    /// Whether the stack over the base branch was computed.
+    pub stack: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheEntry {
+    key: CacheKey,
+    written_at_ms: u128,
+    info: VcsInfo,
+}
+
+/// Longer than a background refresh may take (see `REFRESH_TIMEOUT` in git.rs).
+const REFRESH_LOCK_MS: u128 = 90_000;
+
+fn now_ms() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or_default()
+}
+
+fn mtime_ns(path: &Path) -> Option<u128> {
+    let modified = fs::metadata(path).ok()?.modified().ok()?;
+    Some(modified.duration_since(UNIX_EPOCH).ok()?.as_nanos())
+}
+
+/// Resolves the git directory of a checkout, following the `gitdir:` file
+/// used by worktrees and submodules.
+fn git_dir(root: &Path) -> Option<PathBuf> {
+    let dot_git = root.join(".git");
+    if dot_git.is_dir() {
+        return Some(dot_git);
+    }
+    let contents = fs::read_to_string(&dot_git).ok()?;
+    let dir = contents.trim().strip_prefix("gitdir:")?.trim();
+    Some(root.join(dir))
+}
+
+impl CacheKey {
+    /// Builds the key for the repository containing `cwd`, as found by
+    /// [`find_repo`] for [`crate::git::get_git_info`].
+    pub fn for_cwd(cwd: &str, untracked: bool, stack: bool) -> Option<CacheKey> {
+        let (vcs, root) = find_repo(cwd)?;
+        let mut files = match vcs {
+            Vcs::Jj => vec![
+                root.join(".jj/working_copy/checkout"),
+                root.join(".jj/repo/op_heads/heads"),
+            ],
+            Vcs::Hg => vec![
+                root.join(".hg/dirstate"),
+                root.join(".hg/bookmarks.current"),
+            ],
+            Vcs::Git => vec![],
+        };
+        // Colocated repositories, and jj or hg checkouts read through git
+        // when their backend fails, change with the git metadata too.
+        if let Some(dir) = git_dir(&root) {
+            files.extend([dir.join("index"), dir.join("HEAD")]);
+        } else if vcs == Vcs::Git {
+            return None;
+        }
+        Some(CacheKey {
+            stamps: files.iter().map(|f| mtime_ns(f)).collect(),
+            root,
+            untracked,
+            stack,
+        })
+    }
+
+    fn refresh_lock(&self) -> Option<PathBuf> {
+        Some(self.cache_file()?.with_extension("refresh"))
+    }
+
+    fn cache_file(&self) -> Option<PathBuf> {
+        let mut hasher = DefaultHasher::new();
+        self.root.hash(&mut hasher);
+        let mut path = dirs::cache_dir()?;
+        path.push("foxtail");
+        path.push(format!("vcs-{:016x}.json", hasher.finish()));
+        Some(path)
+    }
+}
+
+fn read_entry(key: &CacheKey) -> Option<CacheEntry> {
+    let contents = fs::read_to_string(key.cache_file()?).ok()?;
+    let entry: CacheEntry = serde_json::from_str(&contents).ok()?;
+    (entry.key.root == key.root).then_some(entry)
+}
+
+/// Returns the cached result if the repository is unchanged and the entry is
+/// younger than `ttl_ms`.
+pub fn load(key: &CacheKey, ttl_ms: u64) -> Option<VcsInfo> {
+    let entry = read_entry(key)?;
+    let fresh = now_ms().saturating_sub(entry.written_at_ms) < ttl_ms as u128;
+    (fresh && entry.key == *key).then_some(entry.info)
+}
+
+/// Returns the last result stored for this repository, however old.
+pub fn load_stale(key: &CacheKey) -> Option<VcsInfo> {
+    read_entry(key).map(|entry| entry.info)
+}
+
+pub fn store(key: &CacheKey, info: &VcsInfo) {
+    let Some(path) = key.cache_file() else {
+        return;
+    };
+    let entry = CacheEntry {
+        key: key.clone(),
+        written_at_ms: now_ms(),
+        info: info.clone(),
+    };
+    if let Ok(json) = serde_json::to_string(&entry) {
+        write_atomic(&path, &json);
+    }
+}
+
+/// Marks a background refresh of the repository as started. Returns false if
+/// another one is already running, so slow repositories don't pile them up.
+/// A lock older than [`REFRESH_LOCK_MS`] is assumed abandoned.
+pub fn claim_refresh(key: &CacheKey) -> bool {
+    let Some(path) = key.refresh_lock() else {
+        return false;
+    };
+    if let Some(dir) = path.parent() {
+        let _ = fs::create_dir_all(dir);
+    }
+    let abandoned =
+        mtime_ns(&path).is_some_and(|t| now_ms().saturating_sub(t / 1_000_000) > REFRESH_LOCK_MS);
+    if abandoned {
+        let _ = fs::remove_file(&path);
+    }
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&path)
+        .is_ok()
+}
+
+pub fn release_refresh(key: &CacheKey) {
+    if let Some(path) = key.refresh_lock() {
+        let _ = fs::remove_file(path);
+    }
+}
+
+fn write_atomic(path: &Path, contents: &str) {
+    if let Some(dir) = path.parent() {
+        let _ = fs::create_dir_all(dir);
+    }
+    // Write then rename so concurrent renders never read a partial file.
+    let tmp = path.with_extension(format!("{}.tmp", std::process::id()));
+    if fs::write(&tmp, contents).is_ok() && fs::rename(&tmp, path).is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+}
+
+fn background_file(session: &str) -> Option<PathBuf> {
+    let mut hasher = DefaultHasher::new();
+    session.hash(&mut hasher);
+    let mut path = dirs::cache_dir()?;
+    path.push("foxtail");
+    path.push(format!("background-{:016x}", hasher.finish()));
+    Some(path)
+}
+
+/// Returns whether the background detected earlier in this terminal session
+/// was light.
+pub fn load_background(session: &str) -> Option<bool> {
+    let contents = fs::read_to_string(background_file(session)?).ok()?;
+    let (stored, light) = contents.rsplit_once('\n')?;
+    (stored == session).then_some(light == "light")
+}
+
+pub fn store_background(session: &str, light: bool) {
+    if let Some(path) = background_file(session) {
+        let value = if light { "light" } else { "dark" };
+        write_atomic(&path, &format!("{}\n{}", session, value));
+    }
+}
@@ -14,6 +14,16 @@ pub enum BackgroundMode {
     Dark,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum ColorDepth {
+    Auto,
+    Truecolor,
+    #[value(name = "256")]
+    Ansi256,
+    #[value(name = "16")]
+    Ansi16,
+}
+
 #[derive(Debug, Parser)]
 #[command(
     name = "foxtail",
@@ -34,7 +44,20 @@ pub struct Args {
     #[arg(long = "background", value_enum, default_value_t = BackgroundMode::Auto)]
     pub background: BackgroundMode,
 
+    /// Colors the terminal supports (defaults to $COLORTERM/$TERM)
+    #[arg(long = "color-depth", value_enum, default_value_t = ColorDepth::Auto)]
+    pub color_depth: ColorDepth,
+
     #[arg(long = "no-git")]
     pub no_git: bool,
+
+    /// Finish a VCS query for this directory and cache it (used internally
+    /// when a render overruns git_timeout_ms)
+    #[arg(long = "refresh-git", hide = true)]
+    pub refresh_git: Option<String>,
+
+    /// Terminal width to fit each line in (defaults to $COLUMNS)
+    #[arg(long = "width")]
+    pub width: Option<usize>,
     // ASCII flag removed: always render the fun fox art when possible
 }
@@ -1,5 +1,32 @@
-use crate::config::{ColorSpec, Colors};
+use crate::cli::ColorDepth;
+use crate::config::{ColorSpec, Colors, Config, SegmentStyle};
 use nu_ansi_term::Color::{self, Rgb};
+use std::sync::OnceLock;
+
+static COLOR_DEPTH: OnceLock<ColorDepth> = OnceLock::new();
+
+/// The xterm defaults for the 16 ANSI colors, in palette order.
+const ANSI16: [(u8, u8, u8); 16] = [
+    (0, 0, 0),
+    (205, 0, 0),
+    (0, 205, 0),
+    (205, 205, 0),
+    (0, 0, 238),
+    (205, 0, 205),
+    (0, 205, 205),
+    (229, 229, 229),
+    (127, 127, 127),
+    (255, 0, 0),
+    (0, 255, 0),
+    (255, 255, 0),
+    (92, 92, 255),
+    (255, 0, 255),
+    (0, 255, 255),
+    (255, 255, 255),
+];
+
+/// Channel levels of the 6x6x6 color cube in the xterm 256-color palette.
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
 
 pub fn hsl_to_rgb(h: f64, s: f64, l: f64) -> (u8, u8, u8) {
     let s = s / 100.0;
@@ -37,22 +64,9 @@ pub fn parse_color(spec: &ColorSpec) -> Option<(u8, u8, u8)> {
                 None
             }
         }
-        ColorSpec::Hex(hex) => {
-            let hex = hex.trim_start_matches('#');
-            if hex.len() == 6 {
-                let r = u8::from_str_radix(&hex[0..2], 16).ok()?;
-                let g = u8::from_str_radix(&hex[2..4], 16).ok()?;
-                let b = u8::from_str_radix(&hex[4..6], 16).ok()?;
-                Some((r, g, b))
-            } else if hex.len() == 3 {
-                let r = u8::from_str_radix(&hex[0..1].repeat(2), 16).ok()?;
-                let g = u8::from_str_radix(&hex[1..2].repeat(2), 16).ok()?;
-                let b = u8::from_str_radix(&hex[2..3].repeat(2), 16).ok()?;
-                Some((r, g, b))
-            } else {
-                None
-            }
-        }
+        ColorSpec::Hex(hex) => parse_hex(hex),
+        // Strings always deserialize as `Hsl`, so hex strings land here too.
+        ColorSpec::Hsl(hex) if hex.trim().starts_with('#') => parse_hex(hex),
         ColorSpec::Hsl(hsl_str) => {
             let hsl_str = hsl_str.trim();
             if !hsl_str.starts_with("hsl(") || !hsl_str.ends_with(')') {
@@ -74,9 +88,124 @@ pub fn parse_color(spec: &ColorSpec) -> Option<(u8, u8, u8)> {
     }
 }
 
+fn parse_hex(hex: &str) -> Option<(u8, u8, u8)> {
+    let hex = hex.trim().trim_start_matches('#');
+    // Slicing below is by byte.
+    if !hex.is_ascii() {
+        return None;
+    }
+    if hex.len() == 6 {
+        let r = u8::from_str_radix(&hex[0..2], 16).ok()?;
+        let g = u8::from_str_radix(&hex[2..4], 16).ok()?;
+        let b = u8::from_str_radix(&hex[4..6], 16).ok()?;
+        Some((r, g, b))
+    } else if hex.len() == 3 {
+        let r = u8::from_str_radix(&hex[0..1].repeat(2), 16).ok()?;
+        let g = u8::from_str_radix(&hex[1..2].repeat(2), 16).ok()?;
+        let b = u8::from_str_radix(&hex[2..3].repeat(2), 16).ok()?;
+        Some((r, g, b))
+    } else {
+        None
+    }
+}
+
+/// Sets the depth every color is quantized to. Only the first call counts.
+pub fn set_color_depth(depth: ColorDepth) {
+    let _ = COLOR_DEPTH.set(depth);
+}
+
+/// Terminals known to show only the 16 basic colors. `COLORTERM` is often lost
+/// over ssh or sudo, so any other `TERM` keeps 24-bit color.
+const TERMS_16_COLORS: [&str; 5] = ["linux", "vt100", "vt220", "ansi", "screen"];
+
+/// Guesses the color depth from `COLORTERM` and `TERM`. Without `TERM`, as
+/// when run outside a terminal, 24-bit color is assumed.
+pub fn color_depth_from_env(colorterm: Option<&str>, term: Option<&str>) -> ColorDepth {
+    if matches!(colorterm, Some("truecolor" | "24bit")) {
+        return ColorDepth::Truecolor;
+    }
+    match term {
+        Some(term) if term.contains("256color") => ColorDepth::Ansi256,
+        Some(term) if TERMS_16_COLORS.contains(&term) => ColorDepth::Ansi16,
+        _ => ColorDepth::Truecolor,
+    }
+}
+
+pub fn detect_color_depth() -> ColorDepth {
+    color_depth_from_env(
+        std::env::var("COLORTERM").ok().as_deref(),
+        std::env::var("TERM").ok().as_deref(),
+    )
+}
+
+fn distance(a: (u8, u8, u8), b: (u8, u8, u8)) -> u32 {
+    let d = |x: u8, y: u8| (x as i32 - y as i32).pow(2) as u32;
+    d(a.0, b.0) + d(a.1, b.1) + d(a.2, b.2)
+}
+
+/// Index of the closest xterm-256 color, among the color cube and the gray
+/// ramp (the first 16 entries vary between terminals and are skipped).
+pub fn nearest_ansi256(rgb: (u8, u8, u8)) -> u8 {
+    let level = |c: u8| {
+        (0..6)
+            .min_by_key(|&i| (CUBE_LEVELS[i] as i32 - c as i32).abs())
+            .unwrap_or(0)
+    };
+    let (r, g, b) = (level(rgb.0), level(rgb.1), level(rgb.2));
+    let cube = (CUBE_LEVELS[r], CUBE_LEVELS[g], CUBE_LEVELS[b]);
+    let average = (rgb.0 as u32 + rgb.1 as u32 + rgb.2 as u32) / 3;
+    let gray_index = (average.saturating_sub(3) / 10).min(23) as u8;
+    let gray = 8 + 10 * gray_index;
+    if distance(rgb, (gray, gray, gray)) < distance(rgb, cube) {
+        232 + gray_index
+    } else {
+        16 + 36 * r as u8 + 6 * g as u8 + b as u8
+    }
+}
+
+/// Index of the closest of the 16 ANSI colors.
+pub fn nearest_ansi16(rgb: (u8, u8, u8)) -> u8 {
+    (0..16u8)
+        .min_by_key(|&i| distance(rgb, ANSI16[i as usize]))
+        .unwrap_or(0)
+}
+
+/// Maps a color to the closest one the terminal can show at `depth`.
+pub fn quantize(rgb: (u8, u8, u8), depth: ColorDepth) -> Color {
+    const NAMED: [Color; 16] = [
+        Color::Black,
+        Color::Red,
+        Color::Green,
+        Color::Yellow,
+        Color::Blue,
+        Color::Purple,
+        Color::Cyan,
+        Color::White,
+        Color::DarkGray,
+        Color::LightRed,
+        Color::LightGreen,
+        Color::LightYellow,
+        Color::LightBlue,
+        Color::LightPurple,
+        Color::LightCyan,
+        Color::LightGray,
+    ];
+    match depth {
+        ColorDepth::Auto | ColorDepth::Truecolor => Rgb(rgb.0, rgb.1, rgb.2),
+        ColorDepth::Ansi256 => Color::Fixed(nearest_ansi256(rgb)),
+        ColorDepth::Ansi16 => NAMED[nearest_ansi16(rgb) as usize],
+    }
+}
+
+/// Builds a terminal color, quantized to the depth set at startup.
+pub fn rgb(r: u8, g: u8, b: u8) -> Color {
+    let depth = COLOR_DEPTH.get().copied().unwrap_or(ColorDepth::Truecolor);
+    quantize((r, g, b), depth)
+}
+
 pub fn color_from_spec(spec: &ColorSpec) -> Color {
     let (r, g, b) = parse_color(spec).expect("Invalid color in palette");
-    Rgb(r, g, b)
+    rgb(r, g, b)
 }
 
 pub fn gradient_green_to_red(percentage: f64, _bg: (u8, u8, u8), is_light_bg: bool) -> Color {
@@ -105,7 +234,7 @@ pub fn gradient_green_to_red(percentage: f64, _bg: (u8, u8, u8), is_light_bg: bo
             yellow.2 + (red.2 - yellow.2) * t,
         )
     };
-    Rgb(r as u8, g as u8, b as u8)
+    rgb(r as u8, g as u8, b as u8)
 }
 
 pub fn get_colors(config_colors: Option<&Colors>, is_light_bg: bool) -> Colors {
@@ -127,7 +256,25 @@ pub fn get_colors(config_colors: Option<&Colors>, is_light_bg: bool) -> Colors {
                 cost: text_color.clone(),
                 cache: text_color.clone(),
                 profile: text_color.clone(),
+                session: text_color.clone(),
+                version: text_color.clone(),
+                output_style: text_color.clone(),
+                api_duration: text_color.clone(),
+                project_dir: text_color.clone(),
+                branch: text_color.clone(),
+                upstream: text_color.clone(),
+                bug: text_color.clone(),
+                revision: text_color.clone(),
+                searchfox: text_color.clone(),
+                mozconfig: text_color.clone(),
+                stack: text_color.clone(),
+                git_state: if is_light_bg {
+                    ColorSpec::Rgb(vec![180, 0, 0])
+                } else {
+                    ColorSpec::Rgb(vec![255, 80, 80])
+                },
                 separator: ColorSpec::Rgb(vec![220, 100, 0]),
+                backgrounds: Default::default(),
             }
         }
     }
@@ -144,9 +291,22 @@ pub fn validate_colors(colors: &Colors) -> Result<(), String> {
         &colors.cost,
         &colors.cache,
         &colors.profile,
+        &colors.session,
+        &colors.version,
+        &colors.output_style,
+        &colors.api_duration,
+        &colors.project_dir,
+        &colors.branch,
+        &colors.upstream,
+        &colors.git_state,
+        &colors.bug,
+        &colors.revision,
+        &colors.searchfox,
+        &colors.mozconfig,
+        &colors.stack,
         &colors.separator,
     ];
-    for spec in specs {
+    for spec in specs.into_iter().chain(colors.backgrounds.values()) {
         if parse_color(spec).is_none() {
             return Err(format!("Invalid color specification: {:?}", spec));
         }
@@ -154,31 +314,146 @@ pub fn validate_colors(colors: &Colors) -> Result<(), String> {
     Ok(())
 }
 
-fn brightness(rgb: (u8, u8, u8)) -> f64 {
-    0.2126 * (rgb.0 as f64) + 0.7152 * (rgb.1 as f64) + 0.0722 * (rgb.2 as f64)
+pub const BLACK: (u8, u8, u8) = (0, 0, 0);
+pub const WHITE: (u8, u8, u8) = (255, 255, 255);
+
+/// WCAG 2 relative luminance, from 0 (black) to 1 (white).
+pub fn relative_luminance(rgb: (u8, u8, u8)) -> f64 {
+    let channel = |c: u8| {
+        let c = c as f64 / 255.0;
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    0.2126 * channel(rgb.0) + 0.7152 * channel(rgb.1) + 0.0722 * channel(rgb.2)
+}
+
+/// WCAG 2 contrast ratio, from 1 (identical) to 21 (black on white).
+pub fn contrast_ratio(a: (u8, u8, u8), b: (u8, u8, u8)) -> f64 {
+    let (la, lb) = (relative_luminance(a), relative_luminance(b));
+    (la.max(lb) + 0.05) / (la.min(lb) + 0.05)
+}
+
+/// Whether dark text reads better than light text on `rgb`.
+pub fn is_light(rgb: (u8, u8, u8)) -> bool {
+    contrast_ratio(rgb, BLACK) > contrast_ratio(rgb, WHITE)
 }
 
-pub fn adjust_colors_for_background(mut colors: Colors, is_light_bg: bool) -> Colors {
-    let adjust = |spec: &ColorSpec, light_bg: bool| -> ColorSpec {
-        if let Some(rgb) = parse_color(spec) {
-            let b = brightness(rgb);
-            if !light_bg && b < 80.0 {
-                return ColorSpec::Rgb(vec![255, 255, 255]);
+/// Inverse of [`hsl_to_rgb`]: hue in degrees, saturation and lightness in
+/// percent.
+pub fn rgb_to_hsl(rgb: (u8, u8, u8)) -> (f64, f64, f64) {
+    let (r, g, b) = (
+        rgb.0 as f64 / 255.0,
+        rgb.1 as f64 / 255.0,
+        rgb.2 as f64 / 255.0,
+    );
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let l = (max + min) / 2.0;
+    let d = max - min;
+    if d == 0.0 {
+        return (0.0, 0.0, l * 100.0);
+    }
+    let s = d / (1.0 - (2.0 * l - 1.0).abs());
+    let h = if max == r {
+        60.0 * ((g - b) / d).rem_euclid(6.0)
+    } else if max == g {
+        60.0 * ((b - r) / d + 2.0)
+    } else {
+        60.0 * ((r - g) / d + 4.0)
+    };
+    (h, s * 100.0, l * 100.0)
+}
+
+/// Moves the lightness of `rgb`, keeping its hue and saturation, by as little
+/// as needed to reach `min_ratio` against `bg`. When neither black nor white
+/// end gets there, the end with the most contrast is used.
+pub fn ensure_contrast(rgb: (u8, u8, u8), bg: (u8, u8, u8), min_ratio: f64) -> (u8, u8, u8) {
+    if contrast_ratio(rgb, bg) >= min_ratio {
+        return rgb;
+    }
+    let (h, s, l) = rgb_to_hsl(rgb);
+    // Luminance is monotonic in lightness, so once the ratio is met on the
+    // way to an end it stays met: bisect for the first such lightness.
+    let towards = |end: f64| {
+        if contrast_ratio(hsl_to_rgb(h, s, end), bg) < min_ratio {
+            return None;
+        }
+        let (mut failing, mut passing) = (l, end);
+        for _ in 0..20 {
+            let mid = (failing + passing) / 2.0;
+            if contrast_ratio(hsl_to_rgb(h, s, mid), bg) >= min_ratio {
+                passing = mid;
+            } else {
+                failing = mid;
             }
-            if light_bg && b > 175.0 {
-                return ColorSpec::Rgb(vec![0, 0, 0]);
+        }
+        Some(passing)
+    };
+    let target = match (towards(0.0), towards(100.0)) {
+        (Some(dark), Some(light)) if l - dark <= light - l => dark,
+        (_, Some(light)) => light,
+        (Some(dark), None) => dark,
+        (None, None) if is_light(bg) => 0.0,
+        (None, None) => 100.0,
+    };
+    hsl_to_rgb(h, s, target)
+}
+
+/// Nudges colors until they reach `min_contrast` against what they are drawn
+/// on: the segment background in the powerline styles, `colors.background`
+/// with `enable_background`, else the terminal.
+pub fn adjust_colors_for_background(mut colors: Colors, cfg: &Config, is_light_bg: bool) -> Colors {
+    let terminal = if is_light_bg { WHITE } else { BLACK };
+    let background = parse_color(&colors.background).unwrap_or(terminal);
+    let powerline = cfg.segment_style != SegmentStyle::Plain;
+    let adjust = |spec: &ColorSpec, key: &str| -> ColorSpec {
+        let bg = if powerline {
+            colors
+                .backgrounds
+                .get(key)
+                .and_then(parse_color)
+                .unwrap_or(background)
+        } else if cfg.enable_background {
+            background
+        } else {
+            terminal
+        };
+        match parse_color(spec) {
+            Some(rgb) => {
+                let (r, g, b) = ensure_contrast(rgb, bg, cfg.min_contrast);
+                if (r, g, b) == rgb {
+                    spec.clone()
+                } else {
+                    ColorSpec::Rgb(vec![r, g, b])
+                }
             }
+            None => spec.clone(),
         }
-        spec.clone()
     };
-    colors.model = adjust(&colors.model, is_light_bg);
-    colors.cwd = adjust(&colors.cwd, is_light_bg);
-    colors.time = adjust(&colors.time, is_light_bg);
-    colors.git_clean = adjust(&colors.git_clean, is_light_bg);
-    colors.delta = adjust(&colors.delta, is_light_bg);
-    colors.cost = adjust(&colors.cost, is_light_bg);
-    colors.cache = adjust(&colors.cache, is_light_bg);
-    colors.profile = adjust(&colors.profile, is_light_bg);
-    colors.separator = adjust(&colors.separator, is_light_bg);
+    colors.model = adjust(&colors.model, "model");
+    colors.cwd = adjust(&colors.cwd, "cwd");
+    colors.time = adjust(&colors.time, "time");
+    colors.git_clean = adjust(&colors.git_clean, "git_clean");
+    colors.delta = adjust(&colors.delta, "delta");
+    colors.cost = adjust(&colors.cost, "cost");
+    colors.cache = adjust(&colors.cache, "cache");
+    colors.profile = adjust(&colors.profile, "profile");
+    colors.session = adjust(&colors.session, "session");
+    colors.version = adjust(&colors.version, "version");
+    colors.output_style = adjust(&colors.output_style, "output_style");
+    colors.api_duration = adjust(&colors.api_duration, "api_duration");
+    colors.project_dir = adjust(&colors.project_dir, "project_dir");
+    colors.branch = adjust(&colors.branch, "branch");
+    colors.upstream = adjust(&colors.upstream, "upstream");
+    colors.git_state = adjust(&colors.git_state, "git_state");
+    colors.bug = adjust(&colors.bug, "bug");
+    colors.revision = adjust(&colors.revision, "revision");
+    colors.searchfox = adjust(&colors.searchfox, "searchfox");
+    colors.mozconfig = adjust(&colors.mozconfig, "mozconfig");
+    colors.stack = adjust(&colors.stack, "stack");
+    colors.separator = adjust(&colors.separator, "separator");
     colors
 }
@@ -1,6 +1,9 @@
-use serde::{Deserialize, Serialize};
+use crate::theme::{load_theme, merge_colors};
+use serde::{Deserialize, Deserializer, Serialize};
 use std::collections::HashMap;
 use std::fs;
+use std::path::Path;
+use toml::{Table, Value};
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 #[serde(untagged)]
@@ -22,7 +25,24 @@ pub struct Colors {
     pub cost: ColorSpec,
     pub cache: ColorSpec,
     pub profile: ColorSpec,
+    pub session: ColorSpec,
+    pub version: ColorSpec,
+    pub output_style: ColorSpec,
+    pub api_duration: ColorSpec,
+    pub project_dir: ColorSpec,
+    pub branch: ColorSpec,
+    pub upstream: ColorSpec,
+    pub git_state: ColorSpec,
+    pub bug: ColorSpec,
+    pub revision: ColorSpec,
+    pub searchfox: ColorSpec,
+    pub mozconfig: ColorSpec,
+    pub stack: ColorSpec,
     pub separator: ColorSpec,
+    /// Per-segment backgrounds for the powerline styles, keyed by the names
+    /// above (e.g. `model`, `cwd`, `git_clean`).
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub backgrounds: HashMap<String, ColorSpec>,
 }
 
 impl Default for Colors {
@@ -37,12 +57,51 @@ impl Default for Colors {
             cost: ColorSpec::Rgb(vec![0, 0, 0]),
             cache: ColorSpec::Rgb(vec![0, 0, 0]),
             profile: ColorSpec::Rgb(vec![0, 0, 0]),
+            session: ColorSpec::Rgb(vec![0, 0, 0]),
+            version: ColorSpec::Rgb(vec![0, 0, 0]),
+            output_style: ColorSpec::Rgb(vec![0, 0, 0]),
+            api_duration: ColorSpec::Rgb(vec![0, 0, 0]),
+            project_dir: ColorSpec::Rgb(vec![0, 0, 0]),
+            branch: ColorSpec::Rgb(vec![0, 0, 0]),
+            upstream: ColorSpec::Rgb(vec![0, 0, 0]),
+            git_state: ColorSpec::Rgb(vec![180, 0, 0]),
+            bug: ColorSpec::Rgb(vec![0, 0, 0]),
+            revision: ColorSpec::Rgb(vec![0, 0, 0]),
+            searchfox: ColorSpec::Rgb(vec![0, 0, 0]),
+            mozconfig: ColorSpec::Rgb(vec![0, 0, 0]),
+            stack: ColorSpec::Rgb(vec![0, 0, 0]),
             separator: ColorSpec::Rgb(vec![220, 100, 0]),
+            backgrounds: HashMap::new(),
         }
     }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+/// How segments are delimited.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SegmentStyle {
+    /// Segments joined by `visual.separator`.
+    #[default]
+    Plain,
+    /// Colored blocks separated by powerline arrows.
+    Powerline,
+    /// Colored blocks with rounded ends.
+    Rounded,
+}
+
+/// How `--background auto` finds the terminal background when
+/// `light_background` is unset.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum BackgroundDetection {
+    /// `$COLORFGBG` only.
+    #[default]
+    Colorfgbg,
+    /// `$COLORFGBG`, else ask the terminal once per terminal session.
+    Osc11,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
 #[allow(dead_code)]
 pub struct Visual {
     #[serde(default)]
@@ -53,7 +112,7 @@ pub struct Visual {
     pub separator: Option<String>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Config {
     #[serde(default = "default_enable_git")]
     pub enable_git: bool,
@@ -74,17 +133,68 @@ pub struct Config {
     #[serde(default = "default_threshold_orange")]
     #[allow(dead_code)]
     pub threshold_orange: u64,
+    /// A bundled theme name or the path to a theme file; `[colors]` entries
+    /// override it.
+    #[serde(default)]
+    pub theme: Option<String>,
     #[serde(default)]
     pub colors: Option<Colors>,
     #[serde(default)]
     #[allow(dead_code)]
     pub visual: Option<Visual>,
-    #[serde(default = "default_format")]
+    /// One line per `\n`; a list of lines is accepted too.
+    #[serde(default = "default_format", deserialize_with = "deserialize_format")]
     pub format: String,
     #[serde(default)]
     pub light_background: Option<bool>,
     #[serde(default)]
+    pub detect_background: BackgroundDetection,
+    #[serde(default)]
     pub warning_message: Option<String>,
+    #[serde(default = "default_git_delta_parts")]
+    pub git_delta_parts: Vec<String>,
+    #[serde(default = "default_git_timeout_ms")]
+    pub git_timeout_ms: u64,
+    #[serde(default = "default_git_cache_ttl_ms")]
+    pub git_cache_ttl_ms: u64,
+    #[serde(default)]
+    pub enable_hyperlinks: bool,
+    #[serde(default = "default_bug_url")]
+    pub bug_url: String,
+    #[serde(default = "default_revision_url")]
+    pub revision_url: String,
+    #[serde(default = "default_searchfox_url")]
+    pub searchfox_url: String,
+    #[serde(default = "default_searchfox_tree")]
+    pub searchfox_tree: String,
+    #[serde(default = "default_stack_bases")]
+    pub stack_bases: Vec<String>,
+    /// Width available to each line; `--width` and `COLUMNS` are used when
+    /// unset.
+    #[serde(default)]
+    pub max_width: Option<usize>,
+    #[serde(default = "default_shrink_order")]
+    pub shrink_order: Vec<String>,
+    #[serde(default)]
+    pub segment_style: SegmentStyle,
+    /// Minimum WCAG contrast ratio of configured colors against their
+    /// background; 1 disables the adjustment.
+    #[serde(default = "default_min_contrast")]
+    pub min_contrast: f64,
+}
+
+/// Accepts either a single format string or a list with one entry per line.
+fn deserialize_format<'de, D: Deserializer<'de>>(d: D) -> Result<String, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Format {
+        Line(String),
+        Lines(Vec<String>),
+    }
+    Ok(match Format::deserialize(d)? {
+        Format::Line(line) => line,
+        Format::Lines(lines) => lines.join("\n"),
+    })
 }
 
 pub fn default_warn_threshold() -> f64 {
@@ -106,8 +216,64 @@ pub fn default_threshold_orange() -> u64 {
     1000
 }
 
+pub fn default_min_contrast() -> f64 {
+    3.0
+}
+
+pub fn default_git_timeout_ms() -> u64 {
+    500
+}
+
+pub fn default_git_cache_ttl_ms() -> u64 {
+    3000
+}
+
+pub fn default_bug_url() -> String {
+    "https://bugzilla.mozilla.org/show_bug.cgi?id={id}".to_string()
+}
+
+pub fn default_revision_url() -> String {
+    "https://phabricator.services.mozilla.com/{id}".to_string()
+}
+
+pub fn default_searchfox_url() -> String {
+    "https://searchfox.org".to_string()
+}
+
+pub fn default_searchfox_tree() -> String {
+    "mozilla-central".to_string()
+}
+
+pub fn default_stack_bases() -> Vec<String> {
+    vec![
+        "central/default".to_string(),
+        "origin/bookmarks/central".to_string(),
+        "origin/main".to_string(),
+        "origin/master".to_string(),
+    ]
+}
+
+pub fn default_shrink_order() -> Vec<String> {
+    vec![
+        "cwd:compact".to_string(),
+        "projectdir:compact".to_string(),
+        "cache".to_string(),
+        "session".to_string(),
+        "outputstyle".to_string(),
+        "apiduration".to_string(),
+        "claudedelta".to_string(),
+        "cost".to_string(),
+        "revision".to_string(),
+        "duration".to_string(),
+    ]
+}
+
+pub fn default_git_delta_parts() -> Vec<String> {
+    vec!["staged".to_string(), "unstaged".to_string()]
+}
+
 pub fn default_format() -> String {
-    "{head} | {model} | {profile} | {cwdcompact} | {duration} | {ctx} | {gitdelta} | {claudedelta} | {cost} | {cache} | {tail}".to_string()
+    "{head} | {model} | {profile} | {cwdcompact} | {duration} | {ctx} | {gitdelta} | {bug} | {revision} | {claudedelta} | {cost} | {cache} | {tail}".to_string()
 }
 
 impl Default for Config {
@@ -121,11 +287,26 @@ impl Default for Config {
             threshold_green: 100,
             threshold_yellow: 500,
             threshold_orange: 1000,
+            theme: None,
             colors: None,
             visual: None,
             format: default_format(),
             light_background: None,
+            detect_background: BackgroundDetection::Colorfgbg,
             warning_message: None,
+            git_delta_parts: default_git_delta_parts(),
+            git_timeout_ms: default_git_timeout_ms(),
+            git_cache_ttl_ms: default_git_cache_ttl_ms(),
+            enable_hyperlinks: false,
+            bug_url: default_bug_url(),
+            revision_url: default_revision_url(),
+            searchfox_url: default_searchfox_url(),
+            searchfox_tree: default_searchfox_tree(),
+            stack_bases: default_stack_bases(),
+            max_width: None,
+            shrink_order: default_shrink_order(),
+            segment_style: SegmentStyle::Plain,
+            min_contrast: default_min_contrast(),
         }
     }
 }
@@ -144,6 +325,28 @@ pub fn get_symbol(key: &str, config: &Config) -> String {
         "cost" => ("💰 $", "$"),
         "cache" => ("🗄 ", "cache:"),
         "profile" => ("👤 ", "profile:"),
+        "session" => ("🔖 ", "session:"),
+        "version" => ("🏷 ", "cc:"),
+        "output_style" => ("🎨 ", "style:"),
+        "api_duration" => ("📡 ", "api:"),
+        "project_dir" => ("🗂 ", "project:"),
+        "input_error" => (" ⚠", " [?]"),
+        "branch" => ("🌿 ", ""),
+        "upstream" => ("🔗 ", "up:"),
+        "staged" => ("●", "S:"),
+        "unstaged" => ("", ""),
+        "untracked" => ("?", "?"),
+        "git_pending" => ("⏳", "…"),
+        "git_state" => ("🚧 ", ""),
+        "conflicts" => ("⚔ ", "conflicts:"),
+        "bug" => ("🐞 ", "bug "),
+        "revision" => ("🔍 ", ""),
+        "searchfox" => ("🦊 ", "sf:"),
+        "mozconfig" => ("🔧 ", "mc:"),
+        "objdir" => (" 📦 ", "→"),
+        "stack" => ("📚 ", "stack:"),
+        "ahead" => ("⇡", "↑"),
+        "behind" => ("⇣", "↓"),
         _ => ("", ""),
     };
     if config.use_emojis {
@@ -153,10 +356,28 @@ pub fn get_symbol(key: &str, config: &Config) -> String {
     }
 }
 
+/// Parses a config file, resolving its `theme` into `colors` with the file's
+/// own `[colors]` entries on top. A theme that fails to load is left for the
+/// caller to report.
+pub fn parse_config(contents: &str, base_dir: Option<&Path>) -> Result<Config, toml::de::Error> {
+    let mut table: Table = toml::from_str(contents)?;
+    let theme = table
+        .get("theme")
+        .and_then(Value::as_str)
+        .and_then(|theme| load_theme(theme, base_dir).ok());
+    if let Some(mut colors) = theme {
+        if let Some(Value::Table(overrides)) = table.remove("colors") {
+            merge_colors(&mut colors, overrides);
+        }
+        table.insert("colors".to_string(), Value::Table(colors));
+    }
+    table.try_into()
+}
+
 pub fn load_config_with_path_override(override_path: Option<&str>) -> (Config, Option<String>) {
     if let Some(path) = override_path {
         if let Ok(contents) = fs::read_to_string(path) {
-            if let Ok(config) = toml::from_str(&contents) {
+            if let Ok(config) = parse_config(&contents, Path::new(path).parent()) {
                 return (config, Some(path.to_string()));
             }
         }
@@ -179,7 +400,7 @@ pub fn load_config_with_path_override(override_path: Option<&str>) -> (Config, O
 
     for path in config_paths.iter().flatten() {
         if let Ok(contents) = fs::read_to_string(path) {
-            if let Ok(config) = toml::from_str(&contents) {
+            if let Ok(config) = parse_config(&contents, path.parent()) {
                 return (config, Some(path.to_string_lossy().to_string()));
             }
         }
@@ -231,6 +452,7 @@ pub fn dump_config() {
         threshold_green: 100,
         threshold_yellow: 500,
         threshold_orange: 1000,
+        theme: Some("fox-light".to_string()),
         colors: Some(Colors::default()),
         visual: Some(Visual {
             head: Some("🦊".to_string()),
@@ -239,15 +461,29 @@ pub fn dump_config() {
         }),
         format: default_format(),
         light_background: Some(true),
+        detect_background: BackgroundDetection::Colorfgbg,
         warning_message: Some(
             "⚠ Context warning: {used_pct}% used in first minute ({used_k}k/{total_k}k) ⚠"
                 .to_string(),
         ),
+        git_delta_parts: default_git_delta_parts(),
+        git_timeout_ms: default_git_timeout_ms(),
+        git_cache_ttl_ms: default_git_cache_ttl_ms(),
+        enable_hyperlinks: true,
+        bug_url: default_bug_url(),
+        revision_url: default_revision_url(),
+        searchfox_url: default_searchfox_url(),
+        searchfox_tree: default_searchfox_tree(),
+        stack_bases: default_stack_bases(),
+        max_width: Some(120),
+        shrink_order: default_shrink_order(),
+        segment_style: SegmentStyle::Plain,
+        min_contrast: default_min_contrast(),
     };
     match toml::to_string(&example_config) {
         Ok(t) => {
             println!("{}", t);
-            println!("\nNote: Colors can be specified as:\n  - RGB arrays: [255, 140, 0]\n  - Hex strings: \"#ff8c00\" or \"#f80\"\n  - HSL strings: \"hsl(30, 100, 50)\"\n\nFormat placeholders:\n  {{head}} {{tail}} {{model}} {{cwd}} {{cwdcompact}} {{duration}}\n  {{ctx}} {{gitdelta}} {{claudedelta}} {{cost}} {{cache}} {{profile}}\n\nThe {{profile}} placeholder shows the value of the ANTHROPIC_PROFILE environment\nvariable. It renders nothing (not even the prefix/emoji) when the variable is\nunset or empty.");
+            println!("\nNote: Colors can be specified as:\n  - RGB arrays: [255, 140, 0]\n  - Hex strings: \"#ff8c00\" or \"#f80\"\n  - HSL strings: \"hsl(30, 100, 50)\"\n\nFormat placeholders:\n  {{head}} {{tail}} {{model}} {{cwd}} {{cwdcompact}} {{duration}}\n  {{ctx}} {{gitdelta}} {{claudedelta}} {{cost}} {{cache}} {{profile}}\n  {{session}} {{ccversion}} {{outputstyle}} {{apiduration}} {{projectdir}}\n  {{branch}} {{upstream}} {{gitstate}} {{bug}} {{revision}}\n  {{searchfox}} {{mozconfig}} {{stack}}\n\n`|` separates segments; empty segments are dropped. Write {{{{ and }}}} (or \\{{,\n\\}}) for literal braces and \\| for a literal pipe. Some placeholders take an\noption: {{cwd:compact}}, {{projectdir:full}}, {{cost:.4}} (decimals) and\n{{session:12}} (characters). Unknown placeholders are reported as errors.\n\n{{name?text}} shows text when the placeholder is empty. {{[...]}} renders its\ncontents only when every placeholder directly inside is non-empty, e.g.\n{{model}}{{[ ({{profile}})]}}. {{[?condition ...]}} renders them only when the\ncondition holds, e.g. {{[?cache_read>50k {{cache}}]}} or {{[?dirty {{gitdelta}}]}}.\nConditions compare one of cache_read, cache_write, cost, ctx (%), duration (s),\nlines_added, lines_removed, git_lines, untracked, conflicts, ahead, behind,\nstack or dirty with >, >=, <, <=, == or !=; a bare name means > 0. dirty only\ncounts untracked files when they are scanned (see git_delta_parts).\n\nformat may also be a list of strings, or contain newlines, to render several\nlines, e.g. format = [\"{{model}} | {{ctx}} | {{cost}}\", \"{{branch}} | {{bug}} | {{cwd}}\"].\nEach line has its own segments and separators; empty lines are dropped.\n\n{{fill}} pushes the rest of its line to the right edge, like a shell RPROMPT:\nformat = \"{{model}} | {{cwd}} {{fill}} {{cost}} | {{duration}}\". When the width is\nunknown (see below) the two sides are joined by the usual separator.\n\nLines wider than the terminal are shrunk by applying shrink_order in turn:\n\"name:option\" switches a placeholder to that option, a bare \"name\" drops it.\nIf a line still does not fit, it is cut with an ellipsis. The width is\n--width, else max_width, else $COLUMNS; without any, lines are never shrunk.\n\nsegment_style = \"powerline\" or \"rounded\" draws each segment as a colored\nblock (needs a Nerd Font/powerline font). A segment takes the background set\nin [colors.backgrounds] for its first placeholder's color, e.g.\nmodel = [58, 58, 138] or git_clean = [40, 90, 40], else colors.background. The\nplain separator is used when color is disabled.\n\ntheme selects a bundled palette (fox-dark, fox-light, solarized-dark,\nsolarized-light, nord) or names a TOML file with the same keys as [colors],\nrelative to the config file. Entries in [colors] override the theme's, e.g.\ntheme = \"fox-dark\" with [colors] model = [255, 255, 255].\n\nColors, configured or default, are darkened or lightened, keeping their hue,\nuntil their WCAG contrast ratio against what they are drawn on reaches\nmin_contrast (default 3; 4.5 is the WCAG AA level for text, 1 turns this off).\nThat is the segment background in the powerline styles, colors.background with\nenable_background, else the terminal background (black or white).\n\n--background auto (the default) uses light_background when set, else\n$COLORFGBG, else dark. With detect_background = \"osc11\" it also asks the\nterminal for its background color (OSC 11) and remembers the answer for the\nterminal session; the query is skipped when the session can't be identified.\n\nColors are written as 24-bit escapes unless $COLORTERM or $TERM say otherwise\n(e.g. TERM=screen-256color); --color-depth truecolor, 256 or 16 forces a\ndepth. Every color is then mapped to the closest one available.\n\nThe {{profile}} placeholder shows the value of the ANTHROPIC_PROFILE environment\nvariable. It renders nothing (not even the prefix/emoji) when the variable is\nunset or empty.\n\ngit_delta_parts selects what {{gitdelta}} reports, in order: \"staged\",\n\"unstaged\", \"total\" (staged and unstaged combined) and \"untracked\" (file\ncount). The default is [\"staged\", \"unstaged\"]: untracked files are only\nscanned when \"untracked\" is listed, which is slow in large trees.\n\ngit_timeout_ms bounds the time spent querying git/jj/hg per render; past it\nthe VCS segments show a placeholder and running commands are killed. 0 waits\nindefinitely. When a query overruns, the last cached result is shown instead\nand the query finishes in the background to update it.\n\ngit_cache_ttl_ms keeps VCS results on disk for that long, as long as the\nrepository's index and HEAD are untouched. 0 disables the cache.\n\nenable_hyperlinks makes {{cwd}}, {{cwdcompact}}, {{bug}}, {{revision}} and\n{{searchfox}} clickable in terminals supporting OSC 8 links (only when color\nis enabled). bug_url and revision_url are templates where {{id}} is replaced by\nthe bug number or the revision (e.g. D123456).\n\n{{searchfox}} shows the current directory relative to the root of a Firefox\ncheckout (found by its mach and moz.configure files), linked to\n(with enable_hyperlinks) <searchfox_url>/<searchfox_tree>/source/<path>. It is empty elsewhere.\n\n{{mozconfig}} shows the mozconfig mach would use in that checkout ($MOZCONFIG,\nelse .mozconfig or mozconfig at the top of the tree) and the objdir it sets.\n\n{{stack}} counts the local commits since the merge-base with the first of\nstack_bases that exists, and shows the age of that base (git only).");
         }
         Err(e) => eprintln!("Error serializing example: {}", e),
     }
@@ -1,7 +1,96 @@
+use crate::cache;
 use crate::config::Config;
+use crate::template::{parse_format, uses};
 use chrono::{DateTime, Duration, Utc};
-use std::path::Path;
-use std::process::Command;
+use serde::{Deserialize, Serialize};
+use std::cell::Cell;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::{mpsc, Mutex};
+use std::thread;
+use std::time::Instant;
+
+const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(2);
+
+/// Hard cap on the VCS commands of a background refresh.
+const REFRESH_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Children spawned under a deadline, so they can be killed from the render
+/// thread once it gives up waiting.
+static RUNNING: Mutex<Vec<Child>> = Mutex::new(Vec::new());
+
+thread_local! {
+    /// Deadline for VCS subprocesses spawned on the current thread.
+    static DEADLINE: Cell<Option<Instant>> = const { Cell::new(None) };
+}
+
+/// Working copy state shared by every VCS backend.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct VcsInfo {
+    #[serde(with = "since_as_timestamp")]
+    pub since_commit: Duration,
+    /// Uncommitted changes in the working copy. For git this is what is not
+    /// staged yet; jj and hg have no index and report everything here.
+    pub added: u64,
+    pub removed: u64,
+    pub staged_added: u64,
+    pub staged_removed: u64,
+    pub untracked: u64,
+    /// Change id and/or bookmark, for backends that expose one.
+    pub label: Option<String>,
+    /// Current branch, or the short commit hash when detached.
+    pub branch: Option<String>,
+    pub upstream: Option<Upstream>,
+    /// In-progress operation such as "rebase 3/7" or "merge".
+    pub operation: Option<String>,
+    pub conflicts: u64,
+    /// Bugzilla bug number and Phabricator revision ("D123456") of the
+    /// current commit.
+    pub bug: Option<String>,
+    pub revision: Option<String>,
+    pub stack: Option<Stack>,
+}
+
+/// Remote-tracking branch and how far the local branch diverged from it.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Upstream {
+    pub name: String,
+    pub ahead: u64,
+    pub behind: u64,
+}
+
+/// Local commits on top of the configured base branch.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Stack {
+    /// Commits between the merge-base and HEAD.
+    pub depth: u64,
+    /// Age of the merge-base commit.
+    #[serde(with = "since_as_timestamp")]
+    pub base_since: Duration,
+}
+
+const SHORT_SHA_LEN: usize = 7;
+/// Shorter numbers after "bug" are more likely prose than Bugzilla ids.
+const MIN_BUG_DIGITS: usize = 4;
+
+/// Stores a "time since" as the Unix timestamp it refers to, so it keeps
+/// ticking when read back from the cache.
+mod since_as_timestamp {
+    use chrono::{DateTime, Duration, Utc};
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(since: &Duration, s: S) -> Result<S::Ok, S::Error> {
+        (Utc::now() - *since).timestamp().serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Duration, D::Error> {
+        let timestamp = i64::deserialize(d)?;
+        Ok(DateTime::from_timestamp(timestamp, 0)
+            .map(|t| Utc::now().signed_duration_since(t))
+            .unwrap_or_default())
+    }
+}
 
 pub fn format_duration_ms(duration_ms: u64) -> String {
     let secs = duration_ms / 1000;
@@ -29,62 +118,729 @@ pub fn format_duration(duration: Duration) -> String {
     }
 }
 
-pub fn get_git_info(cwd: &str, config: &Config) -> Option<(Duration, u64, u64)> {
-    if !config.enable_git {
+/// Version control systems with a backend, in the order they win a tie.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Vcs {
+    Jj,
+    Hg,
+    Git,
+}
+
+/// Returns the repository containing `cwd`: the closest ancestor (inclusive)
+/// with a `.jj`, `.hg` or `.git` entry. jj wins when several are in the same
+/// directory, since colocated jj repositories also have a `.git`.
+pub fn find_repo(cwd: &str) -> Option<(Vcs, PathBuf)> {
+    const MARKERS: [(Vcs, &str); 3] = [(Vcs::Jj, ".jj"), (Vcs::Hg, ".hg"), (Vcs::Git, ".git")];
+    Path::new(cwd).ancestors().find_map(|dir| {
+        MARKERS
+            .iter()
+            .find(|(_, marker)| dir.join(marker).exists())
+            .map(|(vcs, _)| (*vcs, dir.to_path_buf()))
+    })
+}
+
+/// Runs a VCS command and returns its stdout, or `None` if it failed.
+///
+/// When called from [`get_git_info_with_timeout`], the command is killed once
+/// the render's time budget is exhausted.
+pub(crate) fn command_stdout(command: &mut Command) -> Option<String> {
+    let Some(deadline) = DEADLINE.get() else {
+        let output = command.output().ok()?;
+        if !output.status.success() {
+            return None;
+        }
+        return Some(String::from_utf8_lossy(&output.stdout).into_owned());
+    };
+    let mut running = RUNNING.lock().ok()?;
+    // Checked under the lock: the render thread empties `RUNNING` once the
+    // deadline passes, so a child spawned after that would be left running.
+    if Instant::now() >= deadline {
         return None;
     }
-    let jj_path = Path::new(cwd).join(".jj");
-    if jj_path.exists() {
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .ok()?;
+    let stdout = child.stdout.take();
+    let id = child.id();
+    running.push(child);
+    drop(running);
+    // Drain stdout on another thread so a chatty child can't block on a full
+    // pipe while we wait for it.
+    let mut stdout = stdout?;
+    let reader = thread::spawn(move || {
+        let mut buf = Vec::new();
+        let _ = stdout.read_to_end(&mut buf);
+        buf
+    });
+    loop {
+        let mut running = RUNNING.lock().ok()?;
+        // The render thread kills and removes children when it stops waiting.
+        let pos = running.iter().position(|c| c.id() == id)?;
+        match running[pos].try_wait() {
+            Ok(Some(status)) => {
+                // Already reaped by `try_wait`, this returns immediately.
+                let _ = running.swap_remove(pos).wait();
+                drop(running);
+                let buf = reader.join().ok()?;
+                return status
+                    .success()
+                    .then(|| String::from_utf8_lossy(&buf).into_owned());
+            }
+            Ok(None) if Instant::now() < deadline => {
+                drop(running);
+                thread::sleep(POLL_INTERVAL);
+            }
+            _ => {
+                let mut child = running.swap_remove(pos);
+                let _ = child.kill();
+                let _ = child.wait();
+                return None;
+            }
+        }
+    }
+}
+
+fn kill_running_commands() {
+    if let Ok(mut running) = RUNNING.lock() {
+        for mut child in running.drain(..) {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+/// Outcome of a VCS query bounded by `git_timeout_ms`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum VcsLookup {
+    Done(Option<VcsInfo>),
+    /// The query overran its budget; carries the last cached result, if any.
+    TimedOut(Option<VcsInfo>),
+}
+
+impl VcsLookup {
+    pub fn info(&self) -> Option<&VcsInfo> {
+        match self {
+            VcsLookup::Done(info) | VcsLookup::TimedOut(info) => info.as_ref(),
+        }
+    }
+}
+
+/// Runs [`get_git_info`] on a worker thread and gives up after
+/// `git_timeout_ms`, killing any VCS process still running. A timeout of 0
+/// waits indefinitely.
+///
+/// Results are cached on disk per repository for `git_cache_ttl_ms`, as long
+/// as the VCS metadata files are untouched; on timeout the last cached result
+/// is returned, even if stale.
+pub fn get_git_info_with_timeout(cwd: &str, config: &Config) -> VcsLookup {
+    if !config.enable_git || cwd.is_empty() {
+        return VcsLookup::Done(None);
+    }
+    let key = cache::CacheKey::for_cwd(cwd, wants_untracked(config), wants_stack(config));
+    if let Some(key) = key.as_ref().filter(|_| config.git_cache_ttl_ms > 0) {
+        if let Some(info) = cache::load(key, config.git_cache_ttl_ms) {
+            return VcsLookup::Done(Some(info));
+        }
+    }
+    let lookup = if config.git_timeout_ms == 0 {
+        VcsLookup::Done(get_git_info(cwd, config))
+    } else {
+        let deadline = Instant::now() + std::time::Duration::from_millis(config.git_timeout_ms);
+        let (tx, rx) = mpsc::channel();
+        let (worker_cwd, worker_config) = (cwd.to_string(), config.clone());
+        thread::spawn(move || {
+            DEADLINE.set(Some(deadline));
+            let _ = tx.send(get_git_info(&worker_cwd, &worker_config));
+        });
+        match rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            Ok(info) => VcsLookup::Done(info),
+            Err(_) => {
+                kill_running_commands();
+                if let Some(key) = key.as_ref().filter(|_| config.git_cache_ttl_ms > 0) {
+                    spawn_refresh(cwd, key);
+                }
+                VcsLookup::TimedOut(key.as_ref().and_then(cache::load_stale))
+            }
+        }
+    };
+    if let (Some(key), VcsLookup::Done(Some(info))) = (key.as_ref(), &lookup) {
+        if config.git_cache_ttl_ms > 0 {
+            cache::store(key, info);
+        }
+    }
+    lookup
+}
+
+/// Finishes an overrunning lookup in a detached `foxtail --refresh-git`, so
+/// the next render has a result to show even when every query takes longer
+/// than `git_timeout_ms`.
+fn spawn_refresh(cwd: &str, key: &cache::CacheKey) {
+    let Ok(exe) = std::env::current_exe() else {
+        return;
+    };
+    if !cache::claim_refresh(key) {
+        return;
+    }
+    // The original arguments carry `--config`, so the refresh reads the
+    // same configuration.
+    let spawned = Command::new(exe)
+        .args(std::env::args_os().skip(1))
+        .arg("--refresh-git")
+        .arg(cwd)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn();
+    if spawned.is_err() {
+        cache::release_refresh(key);
+    }
+}
+
+/// Body of `foxtail --refresh-git`: queries the repository with a generous
+/// time cap and stores the result for later renders.
+pub fn refresh_cache(cwd: &str, config: &Config) {
+    let key = cache::CacheKey::for_cwd(cwd, wants_untracked(config), wants_stack(config));
+    let Some(key) = key else {
+        return;
+    };
+    DEADLINE.set(Some(Instant::now() + REFRESH_TIMEOUT));
+    if let Some(info) = get_git_info(cwd, config) {
+        cache::store(&key, &info);
+    }
+    cache::release_refresh(&key);
+}
+
+/// Time elapsed since a commit, given its Unix timestamp as printed by a VCS.
+pub(crate) fn since_timestamp(timestamp: &str) -> Option<Duration> {
+    let timestamp: i64 = timestamp.trim().parse().ok()?;
+    let commit_time = DateTime::from_timestamp(timestamp, 0)?;
+    Some(Utc::now().signed_duration_since(commit_time))
+}
+
+/// Parses the summary line of a `--stat` diff, e.g.
+/// ` 3 files changed, 12 insertions(+), 4 deletions(-)`.
+pub fn parse_stat_summary(stat: &str) -> (u64, u64) {
+    let (mut added, mut removed) = (0u64, 0u64);
+    let Some(summary) = stat.lines().rev().find(|l| l.contains("changed")) else {
+        return (0, 0);
+    };
+    for part in summary.split(',') {
+        let mut words = part.split_whitespace();
+        let (Some(n), Some(kind)) = (words.next(), words.next()) else {
+            continue;
+        };
+        let Ok(n) = n.parse::<u64>() else {
+            continue;
+        };
+        if kind.starts_with("insertion") {
+            added += n;
+        } else if kind.starts_with("deletion") {
+            removed += n;
+        }
+    }
+    (added, removed)
+}
+
+pub fn get_git_info(cwd: &str, config: &Config) -> Option<VcsInfo> {
+    if !config.enable_git || cwd.is_empty() {
         return None;
     }
+    // The git path below still serves jj and hg checkouts whose backend fails,
+    // e.g. because the binary is missing.
+    let info = match find_repo(cwd) {
+        Some((Vcs::Jj, root)) => crate::jj::get_jj_info(&root),
+        Some((Vcs::Hg, root)) => crate::hg::get_hg_info(&root),
+        _ => None,
+    };
+    if info.is_some() {
+        return info;
+    }
+    let repo = match gix::discover(cwd) {
+        Ok(repo) => repo,
+        Err(gix::discover::Error::Discover(_)) => return None,
+        Err(_) => return get_git_info_cli(cwd, config),
+    };
+    read_git_repo(&repo, config).or_else(|| get_git_info_cli(cwd, config))
+}
+
+fn wants_untracked(config: &Config) -> bool {
+    config.git_delta_parts.iter().any(|p| p == "untracked") || format_uses(config, "untracked")
+}
+
+/// The merge-base walk can be long on a stale checkout, so it is only done
+/// when the stack is displayed or tested.
+fn wants_stack(config: &Config) -> bool {
+    format_uses(config, "stack")
+}
+
+fn format_uses(config: &Config, name: &str) -> bool {
+    parse_format(&config.format).is_ok_and(|tokens| uses(&tokens, name))
+}
+
+/// Reads everything from a single in-process repository open, without
+/// spawning git.
+pub(crate) fn read_git_repo(repo: &gix::Repository, config: &Config) -> Option<VcsInfo> {
+    use gix::status::index_worktree::Item as WorktreeItem;
+    use gix::status::plumbing::index_as_worktree::{Change, EntryStatus};
+
+    let workdir = repo.workdir()?;
+    let operation = read_operation(repo.git_dir());
+    let head = repo.head_commit().ok()?;
+    let time_since_commit = since_timestamp(&head.time().ok()?.seconds.to_string())?;
+
+    let untracked = if wants_untracked(config) {
+        gix::status::UntrackedFiles::Collapsed
+    } else {
+        gix::status::UntrackedFiles::None
+    };
+    let status = repo
+        .status(gix::progress::Discard)
+        .ok()?
+        .untracked_files(untracked)
+        // Submodules would each need their own status walk; skip them.
+        .index_worktree_submodules(gix::status::Submodule::Given {
+            ignore: gix::submodule::config::Ignore::All,
+            check_dirty: false,
+        })
+        .into_iter(None)
+        .ok()?;
+
+    let mut info = VcsInfo {
+        since_commit: time_since_commit,
+        operation,
+        ..Default::default()
+    };
+    for item in status {
+        match item.ok()? {
+            gix::status::Item::IndexWorktree(WorktreeItem::Modification {
+                rela_path,
+                status: EntryStatus::Conflict { .. },
+                ..
+            }) => {
+                info.conflicts += 1;
+                // Like `git diff`, compare the conflicted file with our side.
+                let ours = repo.index_or_empty().ok().and_then(|index| {
+                    index
+                        .entry_by_path_and_stage(rela_path.as_ref(), gix::index::entry::Stage::Ours)
+                        .map(|entry| entry.id)
+                });
+                let path = workdir.join(gix::path::from_bstr(&rela_path));
+                if let (Some(id), Ok(current)) = (ours, std::fs::read(path)) {
+                    let (a, r) = count_lines(&blob_data(repo, &id), &current);
+                    info.added += a;
+                    info.removed += r;
+                }
+            }
+            gix::status::Item::IndexWorktree(WorktreeItem::Modification {
+                entry,
+                rela_path,
+                status: EntryStatus::Change(change),
+                ..
+            }) => {
+                let index_blob = blob_data(repo, &entry.id);
+                let (a, r) = match change {
+                    Change::Removed => count_lines(&index_blob, &[]),
+                    Change::Modification { .. } => {
+                        let path = workdir.join(gix::path::from_bstr(&rela_path));
+                        match std::fs::read(path) {
+                            Ok(current) => count_lines(&index_blob, &current),
+                            Err(_) => (0, 0),
+                        }
+                    }
+                    _ => (0, 0),
+                };
+                info.added += a;
+                info.removed += r;
+            }
+            gix::status::Item::IndexWorktree(WorktreeItem::DirectoryContents { entry, .. })
+                if entry.status == gix::dir::entry::Status::Untracked =>
+            {
+                info.untracked += 1;
+            }
+            gix::status::Item::TreeIndex(change) => {
+                use gix::diff::index::ChangeRef;
+                let (a, r) = match &change {
+                    ChangeRef::Addition { id, .. } => count_lines(&[], &blob_data(repo, id)),
+                    ChangeRef::Deletion { id, .. } => count_lines(&blob_data(repo, id), &[]),
+                    ChangeRef::Modification {
+                        previous_id, id, ..
+                    } => count_lines(&blob_data(repo, previous_id), &blob_data(repo, id)),
+                    ChangeRef::Rewrite { source_id, id, .. } => {
+                        count_lines(&blob_data(repo, source_id), &blob_data(repo, id))
+                    }
+                };
+                info.staged_added += a;
+                info.staged_removed += r;
+            }
+            _ => {}
+        }
+    }
 
-    Command::new("git")
-        .args(["-C", cwd, "rev-parse", "--git-dir"])
-        .output()
+    let on_branch = if let Ok(Some(name)) = repo.head_name() {
+        info.branch = Some(name.shorten().to_string());
+        info.upstream = read_upstream(repo, name.as_ref(), head.id);
+        true
+    } else {
+        info.branch = Some(head.id.to_hex_with_len(SHORT_SHA_LEN).to_string());
+        false
+    };
+    let message = head.message_raw_sloppy().to_string();
+    let branch = info.branch.as_deref().filter(|_| on_branch);
+    (info.bug, info.revision) = find_bug_and_revision(&message, branch);
+    if wants_stack(config) {
+        info.stack = read_stack(repo, head.id, &config.stack_bases);
+    }
+    Some(info)
+}
+
+/// Counts commits since the merge-base with the first base ref that exists.
+fn read_stack(repo: &gix::Repository, head: gix::ObjectId, bases: &[String]) -> Option<Stack> {
+    let base = bases.iter().find_map(|name| {
+        let id = repo.rev_parse_single(name.as_str()).ok()?;
+        id.object().ok()?.peel_to_commit().ok().map(|c| c.id)
+    })?;
+    let merge_base = repo.merge_base(head, base).ok()?;
+    let base_time = merge_base
+        .object()
         .ok()?
-        .status
-        .success()
-        .then_some(())?;
+        .peel_to_commit()
+        .ok()?
+        .time()
+        .ok()?;
+    let depth = repo
+        .rev_walk([head])
+        .with_hidden([merge_base.detach()])
+        .all()
+        .ok()?
+        .filter_map(Result::ok)
+        .count() as u64;
+    Some(Stack {
+        depth,
+        base_since: since_timestamp(&base_time.seconds.to_string())?,
+    })
+}
 
-    let output = Command::new("git")
-        .args(["-C", cwd, "log", "-1", "--format=%ct"])
-        .output()
+fn read_upstream(
+    repo: &gix::Repository,
+    branch: &gix::refs::FullNameRef,
+    head: gix::ObjectId,
+) -> Option<Upstream> {
+    let tracking = repo
+        .branch_remote_tracking_ref_name(branch, gix::remote::Direction::Fetch)?
         .ok()?;
-    if !output.status.success() {
-        return None;
+    let mut upstream = Upstream {
+        name: tracking.shorten().to_string(),
+        ahead: 0,
+        behind: 0,
+    };
+    // Like git, a configured but missing upstream is shown without counts.
+    let Some(tip) = repo
+        .find_reference(tracking.as_ref())
+        .ok()
+        .and_then(|mut r| r.peel_to_id().ok())
+        .map(|id| id.detach())
+    else {
+        return Some(upstream);
+    };
+    let count = |from: gix::ObjectId, hidden: gix::ObjectId| -> u64 {
+        repo.rev_walk([from])
+            .with_hidden([hidden])
+            .all()
+            .map(|walk| walk.filter_map(Result::ok).count() as u64)
+            .unwrap_or(0)
+    };
+    upstream.ahead = count(head, tip);
+    upstream.behind = count(tip, head);
+    Some(upstream)
+}
+
+fn blob_data(repo: &gix::Repository, id: &gix::oid) -> Vec<u8> {
+    repo.find_object(id)
+        .map(|o| o.detach().data)
+        .unwrap_or_default()
+}
+
+/// Counts inserted and removed lines between two blobs, skipping binary
+/// content the way `git diff --numstat` does.
+pub fn count_lines(old: &[u8], new: &[u8]) -> (u64, u64) {
+    use gix::diff::blob::{diff, intern::InternedInput, sink::Counter, Algorithm};
+    let is_binary = |data: &[u8]| data.iter().take(8000).any(|b| *b == 0);
+    if is_binary(old) || is_binary(new) {
+        return (0, 0);
+    }
+    let input = InternedInput::new(old, new);
+    let counter = diff(Algorithm::Histogram, &input, Counter::default());
+    (counter.insertions as u64, counter.removals as u64)
+}
+
+/// Finds the Bugzilla bug ("Bug 1234567 - …") and Phabricator revision
+/// ("Differential Revision: https://…/D123456") in a commit message, falling
+/// back to the branch or bookmark name (e.g. "bug-1234567", "D123456-fix").
+pub fn find_bug_and_revision(
+    message: &str,
+    branch: Option<&str>,
+) -> (Option<String>, Option<String>) {
+    let bug = find_bug(message).or_else(|| branch.and_then(find_bug));
+    let revision = message
+        .lines()
+        .filter_map(|l| l.trim().strip_prefix("Differential Revision:"))
+        .find_map(|url| url.trim().rsplit('/').next().and_then(revision_id))
+        .or_else(|| {
+            branch.and_then(|b| {
+                b.split(|c: char| !c.is_ascii_alphanumeric())
+                    .find_map(revision_id)
+            })
+        });
+    (bug, revision)
+}
+
+fn revision_id(token: &str) -> Option<String> {
+    let digits = token.strip_prefix('D')?;
+    (!digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit())).then(|| token.to_string())
+}
+
+fn find_bug(text: &str) -> Option<String> {
+    let lower = text.to_ascii_lowercase();
+    let bytes = lower.as_bytes();
+    for (start, _) in lower.match_indices("bug") {
+        if start > 0 && bytes[start - 1].is_ascii_alphanumeric() {
+            continue;
+        }
+        let rest = lower[start + 3..].trim_start_matches([' ', '-', '_', '#', ':']);
+        let digits: String = rest.chars().take_while(char::is_ascii_digit).collect();
+        let followed_by_word = rest[digits.len()..]
+            .chars()
+            .next()
+            .is_some_and(|c| c.is_ascii_alphanumeric());
+        if digits.len() >= MIN_BUG_DIGITS && !followed_by_word {
+            return Some(digits);
+        }
     }
-    let timestamp_str = String::from_utf8_lossy(&output.stdout);
-    let timestamp: i64 = timestamp_str.trim().parse().ok()?;
-    let last_commit_time = DateTime::from_timestamp(timestamp, 0)?;
-    let time_since_commit = Utc::now().signed_duration_since(last_commit_time);
+    None
+}
 
-    let status_output = Command::new("git")
-        .args(["-C", cwd, "status", "--porcelain", "-uno"])
-        .output()
-        .ok()?;
+/// Detects an interrupted rebase, am, merge, cherry-pick, revert or bisect
+/// from the marker files git leaves in its directory.
+pub fn read_operation(git_dir: &Path) -> Option<String> {
+    let read_num =
+        |path: PathBuf| -> Option<u64> { std::fs::read_to_string(path).ok()?.trim().parse().ok() };
+    let with_progress = |name: &str, dir: &Path, step: &str, total: &str| match (
+        read_num(dir.join(step)),
+        read_num(dir.join(total)),
+    ) {
+        (Some(step), Some(total)) => format!("{} {}/{}", name, step, total),
+        _ => name.to_string(),
+    };
+    let rebase_merge = git_dir.join("rebase-merge");
+    if rebase_merge.is_dir() {
+        return Some(with_progress("rebase", &rebase_merge, "msgnum", "end"));
+    }
+    let rebase_apply = git_dir.join("rebase-apply");
+    if rebase_apply.is_dir() {
+        let name = if rebase_apply.join("applying").exists() {
+            "am"
+        } else {
+            "rebase"
+        };
+        return Some(with_progress(name, &rebase_apply, "next", "last"));
+    }
+    let markers = [
+        ("MERGE_HEAD", "merge"),
+        ("CHERRY_PICK_HEAD", "cherry-pick"),
+        ("REVERT_HEAD", "revert"),
+        ("BISECT_LOG", "bisect"),
+    ];
+    markers
+        .iter()
+        .find(|(file, _)| git_dir.join(file).exists())
+        .map(|(_, name)| name.to_string())
+}
+
+/// Subprocess implementation, used when the repository can't be read in
+/// process (e.g. an unsupported extension or index version).
+pub(crate) fn get_git_info_cli(cwd: &str, config: &Config) -> Option<VcsInfo> {
+    let git_dir = command_stdout(Command::new("git").args(["-C", cwd, "rev-parse", "--git-dir"]))?;
+    // `--git-dir` may be relative to `cwd`; joining keeps absolute paths as is.
+    let operation = read_operation(&Path::new(cwd).join(git_dir.trim()));
+
+    let output =
+        command_stdout(Command::new("git").args(["-C", cwd, "log", "-1", "--format=%ct%n%B"]))?;
+    let (timestamp, message) = output.split_once('\n').unwrap_or((&output, ""));
+    let time_since_commit = since_timestamp(timestamp)?;
+
+    let untracked_mode = if wants_untracked(config) {
+        "-unormal"
+    } else {
+        "-uno"
+    };
+    let status = command_stdout(Command::new("git").args([
+        "-C",
+        cwd,
+        "status",
+        "--porcelain=v2",
+        "--branch",
+        untracked_mode,
+    ]))
+    .map(|out| parse_porcelain_v2(&out))
+    .unwrap_or_default();
+    let (added, removed) = if status.unstaged {
+        command_stdout(Command::new("git").args(["-C", cwd, "diff", "--numstat"]))
+            .map(|out| parse_numstat(&out))
+            .unwrap_or_default()
+    } else {
+        (0, 0)
+    };
+    let (staged_added, staged_removed) = if status.staged {
+        command_stdout(Command::new("git").args(["-C", cwd, "diff", "--cached", "--numstat"]))
+            .map(|out| parse_numstat(&out))
+            .unwrap_or_default()
+    } else {
+        (0, 0)
+    };
+    // A detached HEAD reports a commit hash, which is no use as a fallback.
+    let branch = status.branch.as_deref().filter(|_| !status.detached);
+    let (bug, revision) = find_bug_and_revision(message, branch);
+    let stack = if wants_stack(config) {
+        get_stack_cli(cwd, &config.stack_bases)
+    } else {
+        None
+    };
+    Some(VcsInfo {
+        since_commit: time_since_commit,
+        added,
+        removed,
+        staged_added,
+        staged_removed,
+        untracked: status.untracked,
+        label: None,
+        branch: status.branch,
+        upstream: status.upstream,
+        operation,
+        conflicts: status.conflicts,
+        bug,
+        revision,
+        stack,
+    })
+}
+
+fn get_stack_cli(cwd: &str, bases: &[String]) -> Option<Stack> {
+    let base = bases.iter().find(|name| {
+        let spec = format!("{}^{{commit}}", name);
+        command_stdout(Command::new("git").args(["-C", cwd, "rev-parse", "--verify", "-q", &spec]))
+            .is_some()
+    })?;
+    let merge_base =
+        command_stdout(Command::new("git").args(["-C", cwd, "merge-base", "HEAD", base]))?;
+    let merge_base = merge_base.trim();
+    let range = format!("{}..HEAD", merge_base);
+    let depth =
+        command_stdout(Command::new("git").args(["-C", cwd, "rev-list", "--count", &range]))?;
+    let timestamp = command_stdout(Command::new("git").args([
+        "-C",
+        cwd,
+        "log",
+        "-1",
+        "--format=%ct",
+        merge_base,
+    ]))?;
+    Some(Stack {
+        depth: depth.trim().parse().ok()?,
+        base_since: since_timestamp(timestamp.trim())?,
+    })
+}
+
+/// Sums the added and removed columns of `git diff --numstat`. Binary files
+/// report `-` and are skipped.
+pub fn parse_numstat(out: &str) -> (u64, u64) {
     let (mut added, mut removed) = (0u64, 0u64);
-    if status_output.status.success() && !status_output.stdout.is_empty() {
-        let diff_output = Command::new("git")
-            .args(["-C", cwd, "diff", "--numstat"])
-            .output()
-            .ok()?;
-        if !diff_output.status.success() {
-            return Some((time_since_commit, 0, 0));
+    for line in out.lines() {
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        if parts.len() >= 2 {
+            if let Ok(a) = parts[0].parse::<u64>() {
+                added += a;
+            }
+            if let Ok(r) = parts[1].parse::<u64>() {
+                removed += r;
+            }
         }
-        let diff_str = String::from_utf8_lossy(&diff_output.stdout);
-        for line in diff_str.lines() {
-            let parts: Vec<&str> = line.split_whitespace().collect();
-            if parts.len() >= 2 {
-                if let Ok(a) = parts[0].parse::<u64>() {
-                    added += a;
+    }
+    (added, removed)
+}
+
+/// Branch and change information from `git status --porcelain=v2 --branch`.
+#[derive(Debug, Default, PartialEq)]
+pub struct GitStatus {
+    pub branch: Option<String>,
+    pub upstream: Option<Upstream>,
+    pub staged: bool,
+    pub unstaged: bool,
+    pub untracked: u64,
+    pub conflicts: u64,
+    pub detached: bool,
+}
+
+pub fn parse_porcelain_v2(out: &str) -> GitStatus {
+    let mut status = GitStatus::default();
+    let mut oid = None;
+    let mut head = None;
+    for line in out.lines() {
+        let Some(header) = line.strip_prefix("# ") else {
+            // Changed entries are "1 XY …" or "2 XY …", where X is the index
+            // state and Y the worktree state ('.' if unmodified). Unmerged
+            // entries are "u XY …".
+            let mut fields = line.splitn(3, ' ');
+            match (fields.next(), fields.next()) {
+                (Some("?"), _) => status.untracked += 1,
+                (Some("u"), _) => {
+                    status.conflicts += 1;
+                    status.unstaged = true;
+                }
+                (Some("1" | "2"), Some(xy)) => {
+                    let mut xy = xy.chars();
+                    status.staged |= xy.next().is_some_and(|x| x != '.');
+                    status.unstaged |= xy.next().is_some_and(|y| y != '.');
                 }
-                if let Ok(r) = parts[1].parse::<u64>() {
-                    removed += r;
+                _ => {}
+            }
+            continue;
+        };
+        let (key, value) = header.split_once(' ').unwrap_or((header, ""));
+        match key {
+            "branch.oid" => oid = Some(value),
+            "branch.head" => head = Some(value),
+            "branch.upstream" => {
+                status.upstream = Some(Upstream {
+                    name: value.to_string(),
+                    ahead: 0,
+                    behind: 0,
+                })
+            }
+            "branch.ab" => {
+                if let Some(up) = status.upstream.as_mut() {
+                    for count in value.split_whitespace() {
+                        if let Some(n) = count.strip_prefix('+') {
+                            up.ahead = n.parse().unwrap_or(0);
+                        } else if let Some(n) = count.strip_prefix('-') {
+                            up.behind = n.parse().unwrap_or(0);
+                        }
+                    }
                 }
             }
+            _ => {}
         }
     }
-    Some((time_since_commit, added, removed))
+    status.detached = head == Some("(detached)");
+    status.branch = match head {
+        Some("(detached)") => oid
+            .filter(|o| *o != "(initial)")
+            .map(|o| o.chars().take(SHORT_SHA_LEN).collect()),
+        Some(h) => Some(h.to_string()),
+        None => None,
+    };
+    status
 }
 
 pub fn commit_warning_color(
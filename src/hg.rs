@@ -0,0 +1,57 @@
+use crate::git::{
+    command_stdout, find_bug_and_revision, parse_stat_summary, since_timestamp, VcsInfo,
+};
+use std::path::Path;
+use std::process::Command;
+
+const PARENT_TEMPLATE: &str = "{date|hgdate}\n{activebookmark}\n{desc}";
+
+fn hg(root: &str, args: &[&str]) -> Option<String> {
+    // HGPLAIN disables pagers, colors and aliases from the user's hgrc.
+    command_stdout(
+        Command::new("hg")
+            .env("HGPLAIN", "1")
+            .args(["-R", root])
+            .args(args),
+    )
+}
+
+/// Builds the `{gitdelta}` information for a Mercurial checkout: time since
+/// the working directory parent was committed, `hg diff --stat` line counts,
+/// the active bookmark or topic, and the bug/revision of the parent.
+pub fn get_hg_info(root: &Path) -> Option<VcsInfo> {
+    let root = root.to_str()?;
+    let parent = hg(root, &["log", "-r", ".", "-T", PARENT_TEMPLATE])?;
+    let mut lines = parent.lines();
+    // `hgdate` is "<unix timestamp> <tz offset>".
+    let since_commit = since_timestamp(lines.next()?.split_whitespace().next()?)?;
+    let bookmark = lines.next().unwrap_or_default();
+    let description = lines.collect::<Vec<_>>().join("\n");
+    let (added, removed) = hg(root, &["diff", "--stat"])
+        .map(|stat| parse_stat_summary(&stat))
+        .unwrap_or_default();
+    // `{topic}` only exists with the topic extension enabled; ignore failures.
+    let topic = hg(root, &["log", "-r", ".", "-T", "{topic}"]);
+    let label = hg_label(bookmark, topic.as_deref());
+    let (bug, revision) = find_bug_and_revision(&description, label.as_deref());
+    Some(VcsInfo {
+        since_commit,
+        added,
+        removed,
+        label,
+        bug,
+        revision,
+        ..Default::default()
+    })
+}
+
+pub fn hg_label(bookmark: &str, topic: Option<&str>) -> Option<String> {
+    let bookmark = bookmark.trim();
+    if !bookmark.is_empty() {
+        return Some(bookmark.to_string());
+    }
+    topic
+        .map(str::trim)
+        .filter(|t| !t.is_empty())
+        .map(str::to_string)
+}
@@ -0,0 +1,78 @@
+use crate::git::{
+    command_stdout, find_bug_and_revision, parse_stat_summary, since_timestamp, VcsInfo,
+};
+use std::path::Path;
+use std::process::Command;
+
+const PARENT_TIME_TEMPLATE: &str = r#"committer.timestamp().utc().format("%s") ++ "\n""#;
+const CHANGE_ID_TEMPLATE: &str = r#"change_id.shortest(8) ++ "\n" ++ description"#;
+const BOOKMARK_TEMPLATE: &str = r#"local_bookmarks.map(|b| b.name()).join(",") ++ "\n""#;
+
+fn jj(root: &str, snapshot: bool, args: &[&str]) -> Option<String> {
+    let mut full = vec!["--no-pager", "--color", "never", "-R", root];
+    if !snapshot {
+        full.push("--ignore-working-copy");
+    }
+    full.extend_from_slice(args);
+    command_stdout(Command::new("jj").args(&full))
+}
+
+/// Builds the `{gitdelta}` information for a jj repository: time since the
+/// working-copy change's parent was last described, the diff of `@` against
+/// it, the change id plus nearest bookmark, and the bug/revision in the
+/// change description.
+pub fn get_jj_info(root: &Path) -> Option<VcsInfo> {
+    let root = root.to_str()?;
+    // The first command snapshots the working copy, the others reuse it.
+    let change = jj(
+        root,
+        true,
+        &["log", "--no-graph", "-r", "@", "-T", CHANGE_ID_TEMPLATE],
+    )?;
+    let parents = jj(
+        root,
+        false,
+        &["log", "--no-graph", "-r", "@-", "-T", PARENT_TIME_TEMPLATE],
+    )?;
+    let since_commit = parents.lines().filter_map(since_timestamp).min()?;
+    let (added, removed) = jj(root, false, &["diff", "--stat", "-r", "@"])
+        .map(|stat| parse_stat_summary(&stat))
+        .unwrap_or_default();
+    let bookmark = jj(
+        root,
+        false,
+        &[
+            "log",
+            "--no-graph",
+            "-r",
+            "latest(heads(::@ & bookmarks()))",
+            "-T",
+            BOOKMARK_TEMPLATE,
+        ],
+    );
+    let (change_id, description) = change.split_once('\n').unwrap_or((&change, ""));
+    let label = jj_label(change_id, bookmark.as_deref());
+    let (bug, revision) = find_bug_and_revision(description, bookmark.as_deref());
+    Some(VcsInfo {
+        since_commit,
+        added,
+        removed,
+        label,
+        bug,
+        revision,
+        ..Default::default()
+    })
+}
+
+pub fn jj_label(change_id: &str, bookmarks: Option<&str>) -> Option<String> {
+    let change_id = change_id.trim();
+    let bookmark = bookmarks
+        .and_then(|b| b.trim().split(',').next())
+        .filter(|b| !b.is_empty());
+    match (change_id.is_empty(), bookmark) {
+        (true, None) => None,
+        (true, Some(b)) => Some(b.to_string()),
+        (false, None) => Some(change_id.to_string()),
+        (false, Some(b)) => Some(format!("{} {}", change_id, b)),
+    }
+}
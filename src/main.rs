@@ -1,23 +1,62 @@
+mod background;
+mod cache;
 mod cli;
 mod colors;
 mod config;
 mod git;
+mod hg;
+mod jj;
+mod mozilla;
 mod render;
+mod template;
+mod theme;
 mod types;
 
 use clap::Parser;
 use std::io::{self, Read};
+use std::path::Path;
 
 // Re-exports for tests and external use
-pub use cli::{Args, BackgroundMode, ColorMode};
-pub use colors::{adjust_colors_for_background, parse_color};
+pub use background::{parse_colorfgbg, parse_osc11_response, stat_field};
+pub use cache::CacheKey;
+pub use cli::{Args, BackgroundMode, ColorDepth, ColorMode};
+pub use colors::{
+    adjust_colors_for_background, color_depth_from_env, contrast_ratio, ensure_contrast,
+    nearest_ansi16, nearest_ansi256, parse_color, quantize, relative_luminance, rgb_to_hsl,
+};
 pub use config::{
-    default_format, dump_config, get_symbol, load_config_with_path_override, ColorSpec, Colors,
-    Config, Visual,
+    default_bug_url, default_format, default_git_cache_ttl_ms, default_git_delta_parts,
+    default_git_timeout_ms, default_min_contrast, default_revision_url, default_searchfox_tree,
+    default_searchfox_url, default_shrink_order, default_stack_bases, dump_config, get_symbol,
+    load_config_with_path_override, parse_config, BackgroundDetection, ColorSpec, Colors, Config,
+    SegmentStyle, Visual,
+};
+pub use git::{
+    commit_warning_color, count_lines, find_bug_and_revision, find_repo, format_duration_ms,
+    parse_numstat, parse_porcelain_v2, parse_stat_summary, read_operation, GitStatus, Stack,
+    Upstream, Vcs, VcsInfo, VcsLookup,
+};
+pub use hg::hg_label;
+pub use jj::jj_label;
+pub use mozilla::{
+    checkout_relative_path, find_checkout_root, find_mozconfig, mozconfig_label,
+    parse_mozconfig_objdir, searchfox_url, Mozconfig,
+};
+pub use render::{
+    abbreviate_path, build_replacements, color_key, display_width, file_url, git_delta_text,
+    link_url, paint_link, render_branch, render_formatted, render_git, render_git_state,
+    render_input_error, render_mozconfig, render_searchfox, render_stack, render_upstream,
+    render_warning, truncate_to_width, visible_text,
+};
+pub use template::{
+    apply_shrink_step, parse_format, parse_predicate, parse_shrink_step, CompareOp, Placeholder,
+    PlaceholderOption, ShrinkStep, Token, PLACEHOLDERS,
+};
+pub use theme::{load_theme, merge_colors, THEMES};
+pub use types::{
+    parse_status_input, ContextWindow, Cost, CurrentUsage, Model, OutputStyle, StatusInput,
+    Workspace,
 };
-pub use git::{commit_warning_color, format_duration_ms};
-pub use render::{abbreviate_path, render_formatted, render_warning};
-pub use types::{ContextWindow, Cost, CurrentUsage, Model, StatusInput, Workspace};
 
 #[cfg(test)]
 mod tests;
@@ -25,6 +64,12 @@ mod tests;
 fn main() {
     let args = Args::parse();
 
+    if let Some(cwd) = args.refresh_git.as_deref() {
+        let config = load_config_with_path_override(args.config_path.as_deref()).0;
+        git::refresh_cache(cwd, &config);
+        return;
+    }
+
     let version_checker =
         moz_cli_version_check::VersionChecker::new("foxtail", env!("CARGO_PKG_VERSION"));
     version_checker.check_async();
@@ -55,47 +100,76 @@ fn main() {
         }
     };
 
+    colors::set_color_depth(match args.color_depth {
+        ColorDepth::Auto => colors::detect_color_depth(),
+        depth => depth,
+    });
+
     let config_file_override = args.config_path.clone();
 
-    let mut input = String::new();
-    io::stdin()
-        .read_to_string(&mut input)
-        .expect("Failed to read stdin");
+    let mut raw = Vec::new();
+    let _ = io::stdin().read_to_end(&mut raw);
+    let input = String::from_utf8_lossy(&raw);
 
-    let data: StatusInput = match serde_json::from_str(&input) {
-        Ok(d) => d,
-        Err(e) => {
-            eprintln!("Failed to parse JSON: {}", e);
-            std::process::exit(1);
-        }
-    };
+    let (data, parse_error) = parse_status_input(&input);
 
-    let mut config = load_config_with_path_override(config_file_override.as_deref()).0;
+    let (mut config, config_path) = load_config_with_path_override(config_file_override.as_deref());
     if args.no_git {
         config.enable_git = false;
     }
+    config.max_width = args.width.or(config.max_width).or_else(|| {
+        std::env::var("COLUMNS")
+            .ok()
+            .and_then(|c| c.trim().parse().ok())
+    });
     let is_light_bg = match args.background {
         BackgroundMode::Light => true,
         BackgroundMode::Dark => false,
-        BackgroundMode::Auto => config.light_background.unwrap_or(false),
+        // Without color the background does not matter; skip the query.
+        BackgroundMode::Auto => config
+            .light_background
+            .or_else(|| {
+                let query = config.detect_background == BackgroundDetection::Osc11;
+                color_enabled.then(|| background::detect_light_background(query))?
+            })
+            .unwrap_or(false),
     };
-    let mut colors = colors::get_colors(config.colors.as_ref(), is_light_bg);
-    if config.colors.is_some() {
-        colors = adjust_colors_for_background(colors, is_light_bg);
-    }
-    if let Err(e) = colors::validate_colors(&colors) {
-        eprintln!("{}", e);
-        std::process::exit(2);
-    }
-    let bg_rgb = match parse_color(&colors.background) {
-        Some(rgb) => rgb,
-        None => {
-            eprintln!("Invalid background color in configuration");
-            std::process::exit(2);
+    let colors = adjust_colors_for_background(
+        colors::get_colors(config.colors.as_ref(), is_light_bg),
+        &config,
+        is_light_bg,
+    );
+    // Still valid when the colors are not, for the error line below.
+    let bg_rgb = parse_color(&colors.background).unwrap_or((240, 160, 80));
+    // A broken config is reported on the status line itself rather than
+    // leaving it blank.
+    let tokens = match validate_config(&config, config_path.as_deref(), &colors) {
+        Ok(tokens) => tokens,
+        Err(e) => {
+            println!(
+                "{}{}",
+                e,
+                render_input_error(&config, bg_rgb, is_light_bg, color_enabled)
+            );
+            return;
         }
     };
 
-    if data.cost.total_duration_ms / 1000 < 60
+    if parse_error.is_some() {
+        println!(
+            "{}{}",
+            render_formatted(
+                &tokens,
+                &data,
+                &config,
+                &colors,
+                bg_rgb,
+                is_light_bg,
+                color_enabled,
+            ),
+            render_input_error(&config, bg_rgb, is_light_bg, color_enabled),
+        );
+    } else if data.cost.total_duration_ms / 1000 < 60
         && data.context_window.used_percentage > config.warn_high_initial_context_threshold
     {
         println!(
@@ -106,7 +180,7 @@ fn main() {
         println!(
             "{}",
             render_formatted(
-                &config.format,
+                &tokens,
                 &data,
                 &config,
                 &colors,
@@ -119,3 +193,25 @@ fn main() {
 
     version_checker.print_warning();
 }
+
+/// Checks what loading the config does not, returning the parsed format.
+fn validate_config(
+    config: &Config,
+    config_path: Option<&str>,
+    colors: &Colors,
+) -> Result<Vec<Token>, String> {
+    let tokens = parse_format(&config.format).map_err(|e| format!("Invalid format: {}", e))?;
+    if let Some(e) = config
+        .shrink_order
+        .iter()
+        .find_map(|step| parse_shrink_step(step).err())
+    {
+        return Err(format!("Invalid shrink_order: {}", e));
+    }
+    if let Some(theme) = &config.theme {
+        let base_dir = config_path.and_then(|p| Path::new(p).parent());
+        load_theme(theme, base_dir).map_err(|e| format!("Invalid theme: {}", e))?;
+    }
+    colors::validate_colors(colors)?;
+    Ok(tokens)
+}
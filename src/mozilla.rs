@@ -0,0 +1,124 @@
+use std::path::{Path, PathBuf};
+
+/// Files that only exist together at the top of a Firefox source tree.
+const CHECKOUT_MARKERS: [&str; 2] = ["mach", "moz.configure"];
+
+/// Returns the root of the mozilla-central/mozilla-unified checkout
+/// containing `dir`, if any.
+pub fn find_checkout_root(dir: &str) -> Option<PathBuf> {
+    if dir.is_empty() {
+        return None;
+    }
+    Path::new(dir)
+        .ancestors()
+        .find(|d| CHECKOUT_MARKERS.iter().all(|m| d.join(m).is_file()))
+        .map(Path::to_path_buf)
+}
+
+/// Path of `dir` relative to `root`, using `/` separators. Empty for the root
+/// itself.
+pub fn checkout_relative_path(root: &Path, dir: &str) -> Option<String> {
+    let rel = Path::new(dir).strip_prefix(root).ok()?;
+    let parts: Vec<String> = rel
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy().into_owned())
+        .collect();
+    Some(parts.join("/"))
+}
+
+/// Searchfox URL browsing `rel` in `tree`, e.g.
+/// `https://searchfox.org/mozilla-central/source/dom/media`.
+pub fn searchfox_url(base: &str, tree: &str, rel: &str) -> String {
+    let mut url = format!("{}/{}/source", base.trim_end_matches('/'), tree);
+    if !rel.is_empty() {
+        url.push('/');
+        url.push_str(&crate::render::percent_encode_path(rel));
+    }
+    url
+}
+
+/// Mozconfig files mach picks up from the top of the tree when `MOZCONFIG`
+/// is unset.
+const DEFAULT_MOZCONFIGS: [&str; 2] = [".mozconfig", "mozconfig"];
+
+/// The build configuration mach would use in a checkout.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mozconfig {
+    /// Short name: `debug` for `mozconfig.debug`, `default` for `mozconfig`.
+    pub label: String,
+    /// Name of the objdir, if the mozconfig sets one.
+    pub objdir: Option<String>,
+}
+
+/// Resolves the active mozconfig the way mach does: `$MOZCONFIG` (relative
+/// paths are taken from the top of the tree), then the default files.
+pub fn find_mozconfig(root: &Path, env: Option<&str>) -> Option<PathBuf> {
+    if let Some(path) = env.filter(|p| !p.is_empty()) {
+        return Some(root.join(path));
+    }
+    DEFAULT_MOZCONFIGS
+        .iter()
+        .map(|name| root.join(name))
+        .find(|p| p.is_file())
+}
+
+pub fn mozconfig_label(path: &Path) -> String {
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let short = name.trim_start_matches('.');
+    if short == "mozconfig" {
+        return "default".to_string();
+    }
+    ["mozconfig.", "mozconfig-", "mozconfig_"]
+        .iter()
+        .find_map(|prefix| short.strip_prefix(prefix))
+        .filter(|s| !s.is_empty())
+        .unwrap_or(&name)
+        .to_string()
+}
+
+/// Extracts the objdir name from mozconfig contents, from either
+/// `mk_add_options MOZ_OBJDIR=...` or `ac_add_options --with-objdir=...`.
+/// The last assignment wins, as it would in the shell.
+pub fn parse_mozconfig_objdir(contents: &str) -> Option<String> {
+    let mut objdir = None;
+    for line in contents.lines() {
+        let line = line.trim();
+        let value = line
+            .strip_prefix("mk_add_options")
+            .and_then(|rest| rest.trim_start().strip_prefix("MOZ_OBJDIR="))
+            .or_else(|| {
+                line.strip_prefix("ac_add_options")
+                    .and_then(|rest| rest.trim_start().strip_prefix("--with-objdir="))
+            });
+        let Some(value) = value else {
+            continue;
+        };
+        let value = value
+            .split('#')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .trim_matches(|c| c == '"' || c == '\'');
+        if let Some(name) = value.trim_end_matches('/').rsplit('/').next() {
+            if !name.is_empty() && !name.starts_with('@') && !name.starts_with('$') {
+                objdir = Some(name.to_string());
+            }
+        }
+    }
+    objdir
+}
+
+/// Reads the active build configuration of the checkout containing `dir`.
+pub fn read_mozconfig(dir: &str) -> Option<Mozconfig> {
+    let root = find_checkout_root(dir)?;
+    let env = std::env::var("MOZCONFIG").ok();
+    let path = find_mozconfig(&root, env.as_deref())?;
+    let contents = std::fs::read_to_string(&path).ok()?;
+    Some(Mozconfig {
+        label: mozconfig_label(&path),
+        objdir: parse_mozconfig_objdir(&contents),
+    })
+}
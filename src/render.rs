@@ -1,9 +1,19 @@
-use crate::colors::{color_from_spec, gradient_green_to_red};
-use crate::config::{get_symbol, ColorSpec, Colors, Config};
-use crate::git::{commit_warning_color, format_duration, format_duration_ms, get_git_info};
+use crate::colors::{color_from_spec, gradient_green_to_red, parse_color, rgb};
+use crate::config::{get_symbol, ColorSpec, Colors, Config, SegmentStyle};
+use crate::git::{
+    commit_warning_color, format_duration, format_duration_ms, get_git_info_with_timeout, VcsInfo,
+    VcsLookup,
+};
+use crate::mozilla::{
+    checkout_relative_path, find_checkout_root, read_mozconfig, searchfox_url, Mozconfig,
+};
+use crate::template::{
+    apply_shrink_step, parse_shrink_step, Placeholder, PlaceholderOption, ShrinkStep, Token,
+};
 use crate::types::StatusInput;
-use nu_ansi_term::Color::{self, Rgb};
+use nu_ansi_term::Color;
 use nu_ansi_term::Style;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
 use std::path::{Component, Path};
 
@@ -67,10 +77,10 @@ pub fn fox_head(color_enabled: bool) -> String {
     }
     format!(
         "{}{}{}{}",
-        Rgb(0, 0, 0).paint("🭈"),
-        Rgb(250, 248, 230).paint("🭄"),
-        Rgb(255, 180, 40).paint("█"),
-        Rgb(255, 140, 0).paint("█")
+        rgb(0, 0, 0).paint("🭈"),
+        rgb(250, 248, 230).paint("🭄"),
+        rgb(255, 180, 40).paint("█"),
+        rgb(255, 140, 0).paint("█")
     )
 }
 
@@ -82,17 +92,17 @@ pub fn fox_tail(bg: Option<Color>, color_enabled: bool) -> String {
         "{}{}{}{}{}{}{}{}{}{}{}{}",
         bg.map(|b| Style::new().on(b).paint(" "))
             .unwrap_or_else(|| Style::new().paint(" ")),
-        Rgb(255, 140, 0).paint("🭝"),
-        Rgb(255, 160, 20).paint("🭓"),
-        Rgb(255, 245, 200).paint("▇"),
-        Rgb(255, 160, 20).paint("▅"),
-        Rgb(255, 245, 200).paint("▆"),
-        Rgb(255, 160, 20).paint("▄"),
-        Rgb(255, 245, 200).paint("▃"),
-        Rgb(255, 160, 20).paint("▄"),
-        Rgb(255, 245, 200).paint("▃"),
-        Rgb(250, 248, 230).paint("▂"),
-        Rgb(255, 245, 200).paint("🬽")
+        rgb(255, 140, 0).paint("🭝"),
+        rgb(255, 160, 20).paint("🭓"),
+        rgb(255, 245, 200).paint("▇"),
+        rgb(255, 160, 20).paint("▅"),
+        rgb(255, 245, 200).paint("▆"),
+        rgb(255, 160, 20).paint("▄"),
+        rgb(255, 245, 200).paint("▃"),
+        rgb(255, 160, 20).paint("▄"),
+        rgb(255, 245, 200).paint("▃"),
+        rgb(250, 248, 230).paint("▂"),
+        rgb(255, 245, 200).paint("🬽")
     )
 }
 
@@ -118,8 +128,41 @@ pub fn render_ctx(
         .to_string()
 }
 
+/// Builds the "+a -r" part of `{gitdelta}` from the parts selected by
+/// `git_delta_parts`, returning it with the number of changed lines shown.
+pub fn git_delta_text(info: &VcsInfo, cfg: &Config) -> (String, u64) {
+    let mut parts = Vec::new();
+    let mut lines = 0;
+    for part in &cfg.git_delta_parts {
+        let (a, r) = match part.as_str() {
+            "staged" => (info.staged_added, info.staged_removed),
+            "unstaged" => (info.added, info.removed),
+            "total" => (
+                info.added + info.staged_added,
+                info.removed + info.staged_removed,
+            ),
+            "untracked" => {
+                if info.untracked > 0 {
+                    parts.push(format!(
+                        "{}{}",
+                        get_symbol("untracked", cfg),
+                        info.untracked
+                    ));
+                }
+                continue;
+            }
+            _ => continue,
+        };
+        if a + r > 0 {
+            parts.push(format!("{}+{} -{}", get_symbol(part, cfg), a, r));
+            lines += a + r;
+        }
+    }
+    (parts.join(" "), lines)
+}
+
 pub fn render_git(
-    cwd: &str,
+    lookup: &VcsLookup,
     cfg: &Config,
     col: &ColorSpec,
     bg: Option<Color>,
@@ -127,21 +170,37 @@ pub fn render_git(
     light: bool,
     color_enabled: bool,
 ) -> String {
-    get_git_info(cwd, cfg)
-        .map(|(t, a, r)| {
-            let dur = format_duration(t);
-            if a + r > 0 {
-                let text = format!("{} (+{} -{}){}", dur, a, r, get_symbol("git_warning", cfg));
+    if *lookup == VcsLookup::TimedOut(None) {
+        return paint_with(col, get_symbol("git_pending", cfg), bg, color_enabled);
+    }
+    lookup
+        .info()
+        .map(|info| {
+            let dur = format_duration(info.since_commit);
+            let label = info
+                .label
+                .as_ref()
+                .map(|l| format!("{} ", l))
+                .unwrap_or_default();
+            let (delta, lines) = git_delta_text(info, cfg);
+            if !delta.is_empty() {
+                let text = format!(
+                    "{}{} ({}){}",
+                    label,
+                    dur,
+                    delta,
+                    get_symbol("git_warning", cfg)
+                );
                 if !color_enabled {
                     return text;
                 }
-                let c = commit_warning_color(a + r, bg_rgb, light);
+                let c = commit_warning_color(lines, bg_rgb, light);
                 let s = Style::new().fg(c).bold();
                 (if let Some(b) = bg { s.on(b) } else { s })
                     .paint(text)
                     .to_string()
             } else {
-                let text = format!("{}{}", get_symbol("git_clean", cfg), dur);
+                let text = format!("{}{}{}", label, get_symbol("git_clean", cfg), dur);
                 if !color_enabled {
                     return text;
                 }
@@ -154,14 +213,309 @@ pub fn render_git(
         .unwrap_or_default()
 }
 
+pub fn render_branch(
+    info: Option<&VcsInfo>,
+    cfg: &Config,
+    col: &ColorSpec,
+    bg: Option<Color>,
+    color_enabled: bool,
+) -> String {
+    info.and_then(|i| i.branch.as_ref())
+        .map(|b| {
+            paint_with(
+                col,
+                format!("{}{}", get_symbol("branch", cfg), b),
+                bg,
+                color_enabled,
+            )
+        })
+        .unwrap_or_default()
+}
+
+pub fn render_upstream(
+    info: Option<&VcsInfo>,
+    cfg: &Config,
+    col: &ColorSpec,
+    bg: Option<Color>,
+    color_enabled: bool,
+) -> String {
+    let Some(up) = info.and_then(|i| i.upstream.as_ref()) else {
+        return String::new();
+    };
+    let mut text = format!("{}{}", get_symbol("upstream", cfg), up.name);
+    if up.ahead > 0 {
+        text.push_str(&format!(" {}{}", get_symbol("ahead", cfg), up.ahead));
+    }
+    if up.behind > 0 {
+        text.push_str(&format!(" {}{}", get_symbol("behind", cfg), up.behind));
+    }
+    paint_with(col, text, bg, color_enabled)
+}
+
+/// Stack depth over the base branch and the age of the base, e.g.
+/// `stack:5 (3d2h)`. Empty when HEAD is on the base itself.
+pub fn render_stack(
+    info: Option<&VcsInfo>,
+    cfg: &Config,
+    col: &ColorSpec,
+    bg: Option<Color>,
+    color_enabled: bool,
+) -> String {
+    let Some(stack) = info.and_then(|i| i.stack.as_ref()).filter(|s| s.depth > 0) else {
+        return String::new();
+    };
+    let text = format!(
+        "{}{} ({})",
+        get_symbol("stack", cfg),
+        stack.depth,
+        format_duration(stack.base_since)
+    );
+    paint_with(col, text, bg, color_enabled)
+}
+
+pub fn render_git_state(
+    info: Option<&VcsInfo>,
+    cfg: &Config,
+    col: &ColorSpec,
+    bg: Option<Color>,
+    color_enabled: bool,
+) -> String {
+    let Some(info) = info else {
+        return String::new();
+    };
+    let mut parts = Vec::new();
+    if let Some(op) = info.operation.as_ref() {
+        parts.push(op.to_uppercase());
+    }
+    if info.conflicts > 0 {
+        parts.push(format!(
+            "{}{}",
+            get_symbol("conflicts", cfg),
+            info.conflicts
+        ));
+    }
+    if parts.is_empty() {
+        return String::new();
+    }
+    let text = format!("{}{}", get_symbol("git_state", cfg), parts.join(" "));
+    paint_with(col, text, bg, color_enabled)
+}
+
 pub fn paint_with(col: &ColorSpec, text: String, bg: Option<Color>, color_enabled: bool) -> String {
+    paint_link(col, text, None, bg, color_enabled)
+}
+
+/// Like [`paint_with`], additionally wrapping the text in an OSC 8 hyperlink.
+/// Links are escape sequences too, so they are dropped when color is off.
+pub fn paint_link(
+    col: &ColorSpec,
+    text: String,
+    url: Option<String>,
+    bg: Option<Color>,
+    color_enabled: bool,
+) -> String {
     if !color_enabled {
         return text;
     }
     let s = crate::colors::color_from_spec(col).bold();
-    (if let Some(b) = bg { s.on(b) } else { s })
-        .paint(text)
-        .to_string()
+    let painted = (if let Some(b) = bg { s.on(b) } else { s }).paint(text);
+    match url {
+        Some(url) => painted.hyperlink(url).to_string(),
+        None => painted.to_string(),
+    }
+}
+
+/// Expands a URL template such as `bug_url`, if hyperlinks are enabled.
+pub fn link_url(cfg: &Config, template: &str, id: &str) -> Option<String> {
+    cfg.enable_hyperlinks.then(|| template.replace("{id}", id))
+}
+
+/// Percent-encodes everything in a path that isn't unreserved, keeping `/`.
+pub fn percent_encode_path(path: &str) -> String {
+    let mut out = String::with_capacity(path.len());
+    for b in path.bytes() {
+        if b.is_ascii_alphanumeric() || b"/-_.~".contains(&b) {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{:02X}", b));
+        }
+    }
+    out
+}
+
+/// `file://` URL for a local path.
+pub fn file_url(path: &str) -> String {
+    format!("file://{}", percent_encode_path(path))
+}
+
+/// Active mozconfig label and objdir, e.g. `mc:debug→obj-debug`.
+pub fn render_mozconfig(
+    mozconfig: Option<&Mozconfig>,
+    cfg: &Config,
+    col: &ColorSpec,
+    bg: Option<Color>,
+    color_enabled: bool,
+) -> String {
+    let Some(m) = mozconfig else {
+        return String::new();
+    };
+    let mut text = format!("{}{}", get_symbol("mozconfig", cfg), m.label);
+    if let Some(objdir) = &m.objdir {
+        text.push_str(&get_symbol("objdir", cfg));
+        text.push_str(objdir);
+    }
+    paint_with(col, text, bg, color_enabled)
+}
+
+/// Repo-relative path of the current directory inside a Firefox checkout,
+/// linked to Searchfox. Empty outside a checkout.
+pub fn render_searchfox(
+    dir: &str,
+    cfg: &Config,
+    col: &ColorSpec,
+    bg: Option<Color>,
+    color_enabled: bool,
+) -> String {
+    let Some(root) = find_checkout_root(dir) else {
+        return String::new();
+    };
+    let Some(rel) = checkout_relative_path(&root, dir) else {
+        return String::new();
+    };
+    let url = cfg
+        .enable_hyperlinks
+        .then(|| searchfox_url(&cfg.searchfox_url, &cfg.searchfox_tree, &rel));
+    let label = if rel.is_empty() {
+        cfg.searchfox_tree.as_str()
+    } else {
+        rel.as_str()
+    };
+    paint_link(
+        col,
+        format!("{}{}", get_symbol("searchfox", cfg), label),
+        url,
+        bg,
+        color_enabled,
+    )
+}
+
+/// Current directory, linked to itself when hyperlinks are enabled.
+pub fn render_cwd(
+    dir: &str,
+    compact: bool,
+    cfg: &Config,
+    col: &ColorSpec,
+    bg: Option<Color>,
+    color_enabled: bool,
+) -> String {
+    if dir.is_empty() {
+        return String::new();
+    }
+    let shown = if compact {
+        abbreviate_path(dir)
+    } else {
+        dir.to_string()
+    };
+    let url = cfg.enable_hyperlinks.then(|| file_url(dir));
+    let text = format!("{}{}", get_symbol("cwd", cfg), shown);
+    paint_link(col, text, url, bg, color_enabled)
+}
+
+pub fn render_project_dir(
+    dir: Option<&str>,
+    compact: bool,
+    cfg: &Config,
+    col: &ColorSpec,
+    bg: Option<Color>,
+    color_enabled: bool,
+) -> String {
+    let Some(dir) = dir.filter(|p| !p.is_empty()) else {
+        return String::new();
+    };
+    let shown = if compact {
+        abbreviate_path(dir)
+    } else {
+        dir.to_string()
+    };
+    let text = format!("{}{}", get_symbol("project_dir", cfg), shown);
+    paint_with(col, text, bg, color_enabled)
+}
+
+pub fn render_cost(
+    cost: f64,
+    precision: usize,
+    cfg: &Config,
+    col: &ColorSpec,
+    bg: Option<Color>,
+    color_enabled: bool,
+) -> String {
+    let text = format!("{}{:.*}", get_symbol("cost", cfg), precision, cost);
+    paint_with(col, text, bg, color_enabled)
+}
+
+/// Leading characters of the session id, which are enough to tell sessions
+/// apart.
+pub fn render_session(
+    id: Option<&str>,
+    len: usize,
+    cfg: &Config,
+    col: &ColorSpec,
+    bg: Option<Color>,
+    color_enabled: bool,
+) -> String {
+    let Some(id) = id.filter(|s| !s.is_empty()) else {
+        return String::new();
+    };
+    let text = format!(
+        "{}{}",
+        get_symbol("session", cfg),
+        id.chars().take(len).collect::<String>()
+    );
+    paint_with(col, text, bg, color_enabled)
+}
+
+/// Renders a placeholder that was given an option; the values built by
+/// [`build_replacements`] cover the defaults.
+fn render_with_option(
+    p: &Placeholder,
+    d: &StatusInput,
+    cfg: &Config,
+    col: &Colors,
+    bg: Option<Color>,
+    color_enabled: bool,
+) -> Option<String> {
+    let option = p.option?;
+    let compact = !matches!(option, PlaceholderOption::Full);
+    Some(match (p.name.as_str(), option) {
+        ("cwd", _) => render_cwd(
+            &d.workspace.current_dir,
+            compact,
+            cfg,
+            &col.cwd,
+            bg,
+            color_enabled,
+        ),
+        ("projectdir", _) => render_project_dir(
+            d.workspace.project_dir.as_deref(),
+            compact,
+            cfg,
+            &col.project_dir,
+            bg,
+            color_enabled,
+        ),
+        ("cost", PlaceholderOption::Precision(n)) => {
+            render_cost(d.cost.total_cost_usd, n, cfg, &col.cost, bg, color_enabled)
+        }
+        ("session", PlaceholderOption::Length(n)) => render_session(
+            d.session_id.as_deref(),
+            n,
+            cfg,
+            &col.session,
+            bg,
+            color_enabled,
+        ),
+        _ => return None,
+    })
 }
 
 #[rustfmt::skip]
@@ -169,29 +523,54 @@ pub fn build_replacements<'a>(
     d: &'a StatusInput,
     cfg: &'a Config,
     col: &'a Colors,
-    bg: Option<Color>,
     bg_rgb: (u8, u8, u8),
     light: bool,
     color_enabled: bool,
-) -> [(&'static str, String); 12] {
+    lookup: &VcsLookup,
+) -> [(&'static str, String); 25] {
     let sym = |k| get_symbol(k, cfg);
+    let bg = background(cfg, bg_rgb, color_enabled);
+    let vcs = lookup.info();
     [
         ("head",        cfg.visual.as_ref().and_then(|v| v.head.clone()).unwrap_or_else(|| fox_head(color_enabled))),
         ("tail",        cfg.visual.as_ref().and_then(|v| v.tail.clone()).unwrap_or_else(|| fox_tail(bg, color_enabled))),
         ("model",       paint_with(&col.model, d.model.display_name.clone(), bg, color_enabled)),
-        ("cwd",         paint_with(&col.cwd, format!("{}{}", sym("cwd"), d.workspace.current_dir), bg, color_enabled)),
-        ("cwdcompact",  paint_with(&col.cwd, format!("{}{}", sym("cwd"), abbreviate_path(&d.workspace.current_dir)), bg, color_enabled)),
+        ("cwd",         render_cwd(&d.workspace.current_dir, false, cfg, &col.cwd, bg, color_enabled)),
+        ("cwdcompact",  render_cwd(&d.workspace.current_dir, true, cfg, &col.cwd, bg, color_enabled)),
         ("duration",    paint_with(&col.time, format!("{}{}", sym("time"), format_duration_ms(d.cost.total_duration_ms)), bg, color_enabled)),
         ("ctx",         render_ctx(d.context_window.used_percentage, d.context_window.context_window_size, &sym("context"), bg, bg_rgb, light, color_enabled)),
         ("claudedelta", paint_with(&col.delta, format!("{}+{} -{}", sym("delta"), d.cost.total_lines_added, d.cost.total_lines_removed), bg, color_enabled)),
-        ("gitdelta",    render_git(&d.cwd, cfg, &col.git_clean, bg, bg_rgb, light, color_enabled)),
-        ("cost",        paint_with(&col.cost, format!("{}{:.2}", sym("cost"), d.cost.total_cost_usd), bg, color_enabled)),
+        ("gitdelta",    render_git(lookup, cfg, &col.git_clean, bg, bg_rgb, light, color_enabled)),
+        ("branch",      render_branch(vcs, cfg, &col.branch, bg, color_enabled)),
+        ("upstream",    render_upstream(vcs, cfg, &col.upstream, bg, color_enabled)),
+        ("stack",       render_stack(vcs, cfg, &col.stack, bg, color_enabled)),
+        ("gitstate",    render_git_state(vcs, cfg, &col.git_state, bg, color_enabled)),
+        ("bug",         vcs.and_then(|i| i.bug.as_ref())
+            .map(|b| paint_link(&col.bug, format!("{}{}", sym("bug"), b), link_url(cfg, &cfg.bug_url, b), bg, color_enabled))
+            .unwrap_or_default()),
+        ("revision",    vcs.and_then(|i| i.revision.as_ref())
+            .map(|r| paint_link(&col.revision, format!("{}{}", sym("revision"), r), link_url(cfg, &cfg.revision_url, r), bg, color_enabled))
+            .unwrap_or_default()),
+        ("searchfox",   render_searchfox(&d.workspace.current_dir, cfg, &col.searchfox, bg, color_enabled)),
+        ("mozconfig",   render_mozconfig(read_mozconfig(&d.cwd).as_ref(), cfg, &col.mozconfig, bg, color_enabled)),
+        ("cost",        render_cost(d.cost.total_cost_usd, 2, cfg, &col.cost, bg, color_enabled)),
         ("cache",       d.context_window.current_usage.as_ref()
             .map(|u| paint_with(&col.cache, format!("{}r:{:.0}k w:{:.0}k", sym("cache"), u.cache_read_input_tokens as f64 / 1000.0, u.cache_creation_input_tokens as f64 / 1000.0), bg, color_enabled))
             .unwrap_or_default()),
         ("profile",     std::env::var("ANTHROPIC_PROFILE").ok().filter(|p| !p.is_empty())
             .map(|p| paint_with(&col.profile, format!("{}{}", sym("profile"), p), bg, color_enabled))
             .unwrap_or_default()),
+        ("session",     render_session(d.session_id.as_deref(), 8, cfg, &col.session, bg, color_enabled)),
+        ("ccversion",   d.version.as_deref().filter(|v| !v.is_empty())
+            .map(|v| paint_with(&col.version, format!("{}{}", sym("version"), v), bg, color_enabled))
+            .unwrap_or_default()),
+        ("outputstyle", d.output_style.as_ref().map(|o| o.name.as_str()).filter(|n| !n.is_empty())
+            .map(|n| paint_with(&col.output_style, format!("{}{}", sym("output_style"), n), bg, color_enabled))
+            .unwrap_or_default()),
+        ("apiduration", d.cost.total_api_duration_ms
+            .map(|ms| paint_with(&col.api_duration, format!("{}{}", sym("api_duration"), format_duration_ms(ms)), bg, color_enabled))
+            .unwrap_or_default()),
+        ("projectdir",  render_project_dir(d.workspace.project_dir.as_deref(), true, cfg, &col.project_dir, bg, color_enabled)),
     ]
 }
 
@@ -225,7 +604,7 @@ pub fn render_warning(
     if !color_enabled {
         return text;
     }
-    let bg = Rgb(bg_rgb.0, bg_rgb.1, bg_rgb.2);
+    let bg = rgb(bg_rgb.0, bg_rgb.1, bg_rgb.2);
     let s = Style::new()
         .fg(gradient_green_to_red(100.0, bg_rgb, light))
         .bold();
@@ -234,8 +613,304 @@ pub fn render_warning(
         .to_string()
 }
 
+/// Marker appended to the status line when the input could only be partially
+/// parsed, so a schema change is noticed without blanking the whole line.
+pub fn render_input_error(
+    cfg: &Config,
+    bg_rgb: (u8, u8, u8),
+    light: bool,
+    color_enabled: bool,
+) -> String {
+    let text = get_symbol("input_error", cfg);
+    if !color_enabled {
+        return text;
+    }
+    let bg = rgb(bg_rgb.0, bg_rgb.1, bg_rgb.2);
+    let s = Style::new()
+        .fg(gradient_green_to_red(100.0, bg_rgb, light))
+        .bold();
+    (if cfg.enable_background { s.on(bg) } else { s })
+        .paint(text)
+        .to_string()
+}
+
+/// Background of every value, when enabled. The powerline styles paint
+/// backgrounds per segment instead.
+fn background(cfg: &Config, bg_rgb: (u8, u8, u8), color_enabled: bool) -> Option<Color> {
+    (cfg.enable_background && color_enabled && cfg.segment_style == SegmentStyle::Plain)
+        .then_some(rgb(bg_rgb.0, bg_rgb.1, bg_rgb.2))
+}
+
+/// Name of the [`Colors`] entry a placeholder is painted with, which also
+/// selects its background in the powerline styles.
+pub fn color_key(placeholder: &str) -> Option<&'static str> {
+    Some(match placeholder {
+        "model" => "model",
+        "cwd" | "cwdcompact" => "cwd",
+        "duration" => "time",
+        "claudedelta" => "delta",
+        "gitdelta" => "git_clean",
+        "branch" => "branch",
+        "upstream" => "upstream",
+        "stack" => "stack",
+        "gitstate" => "git_state",
+        "bug" => "bug",
+        "revision" => "revision",
+        "searchfox" => "searchfox",
+        "mozconfig" => "mozconfig",
+        "cost" => "cost",
+        "cache" => "cache",
+        "profile" => "profile",
+        "session" => "session",
+        "ccversion" => "version",
+        "outputstyle" => "output_style",
+        "apiduration" => "api_duration",
+        "projectdir" => "project_dir",
+        _ => return None,
+    })
+}
+
+/// Text between two separators.
+#[derive(Debug, Default)]
+struct Segment {
+    text: String,
+    /// First placeholder that rendered something, which picks the segment
+    /// background in the powerline styles.
+    lead: Option<String>,
+}
+
+/// Applies a background under already painted text, re-applying it after
+/// each reset so it spans the whole segment.
+fn on_background(text: &str, bg: Color) -> String {
+    const RESET: &str = "\x1b[0m";
+    let on = Style::new().on(bg).prefix().to_string();
+    format!(
+        "{}{}{}",
+        on,
+        text.replace(RESET, &format!("{}{}", RESET, on)),
+        RESET
+    )
+}
+
+/// Joins segments as colored blocks, with transition glyphs colored from the
+/// adjacent backgrounds. `right` uses left-pointing glyphs, for the part of a
+/// line after `{fill}`.
+fn join_powerline(
+    segments: &[(String, Color)],
+    style: SegmentStyle,
+    separator: Color,
+    right: bool,
+) -> String {
+    let (solid, thin) = match (style, right) {
+        (SegmentStyle::Rounded, false) => ("\u{e0b4}", "\u{e0b5}"),
+        (SegmentStyle::Rounded, true) => ("\u{e0b6}", "\u{e0b7}"),
+        (_, false) => ("\u{e0b0}", "\u{e0b1}"),
+        (_, true) => ("\u{e0b2}", "\u{e0b3}"),
+    };
+    let mut out = String::new();
+    let mut prev: Option<Color> = None;
+    for (text, bg) in segments {
+        let glyph = match prev {
+            // Same background on both sides: a thin divider is all that shows.
+            Some(p) if p == *bg => Style::new().fg(separator).on(*bg).paint(thin),
+            Some(p) if right => Style::new().fg(*bg).on(p).paint(solid),
+            Some(p) => Style::new().fg(p).on(*bg).paint(solid),
+            None if right => Style::new().fg(*bg).paint(solid),
+            None => Style::new().paint(""),
+        };
+        out.push_str(&glyph.to_string());
+        out.push_str(&on_background(&format!(" {} ", text), *bg));
+        prev = Some(*bg);
+    }
+    if let (Some(last), false) = (prev, right) {
+        out.push_str(&Style::new().fg(last).paint(solid).to_string());
+    }
+    out
+}
+
+/// Text left once ANSI escape sequences (colors and OSC 8 links) are removed.
+pub fn visible_text(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            // CSI: parameters up to a final byte in '@'..='~'.
+            Some('[') => {
+                for n in chars.by_ref() {
+                    if ('@'..='~').contains(&n) {
+                        break;
+                    }
+                }
+            }
+            // OSC: up to BEL or ST (ESC \).
+            Some(']') => {
+                while let Some(n) = chars.next() {
+                    if n == '\x07' || (n == '\x1b' && chars.next_if_eq(&'\\').is_some()) {
+                        break;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    out
+}
+
+/// Number of terminal columns the text occupies once escapes are removed,
+/// counting wide glyphs (CJK, most emoji) as two.
+pub fn display_width(s: &str) -> usize {
+    UnicodeWidthStr::width(visible_text(s).as_str())
+}
+
+/// Cuts `s` to at most `width` columns, ending with an ellipsis. Escape
+/// sequences are kept, and colors and links still open where the text is cut
+/// are closed.
+pub fn truncate_to_width(s: &str, width: usize) -> String {
+    if display_width(s) <= width {
+        return s.to_string();
+    }
+    let budget = width.saturating_sub(1);
+    let mut out = String::with_capacity(s.len());
+    let mut used = 0;
+    let mut styled = false;
+    let mut link_open = false;
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            let mut seq = String::from(c);
+            match chars.next() {
+                Some('[') => {
+                    seq.push('[');
+                    for n in chars.by_ref() {
+                        seq.push(n);
+                        if ('@'..='~').contains(&n) {
+                            break;
+                        }
+                    }
+                    styled = seq != "\x1b[0m" && seq != "\x1b[m";
+                }
+                Some(']') => {
+                    seq.push(']');
+                    while let Some(n) = chars.next() {
+                        seq.push(n);
+                        if n == '\x07' {
+                            break;
+                        }
+                        if n == '\x1b' && chars.peek() == Some(&'\\') {
+                            seq.push(chars.next().unwrap_or('\\'));
+                            break;
+                        }
+                    }
+                    if let Some(params) = seq.strip_prefix("\x1b]8;") {
+                        // "8;params;URL": an empty URL closes the link.
+                        let url = params.split_once(';').map_or("", |(_, u)| u);
+                        link_open = !url.trim_end_matches(['\x07', '\x1b', '\\']).is_empty();
+                    }
+                }
+                Some(n) => seq.push(n),
+                None => {}
+            }
+            out.push_str(&seq);
+            continue;
+        }
+        let w = UnicodeWidthChar::width(c).unwrap_or(0);
+        if used + w > budget {
+            break;
+        }
+        used += w;
+        out.push(c);
+    }
+    if link_open {
+        out.push_str("\x1b]8;;\x1b\\");
+    }
+    if styled {
+        out.push_str("\x1b[0m");
+    }
+    out.push('…');
+    out
+}
+
+/// Numbers tested by `{[?predicate ...]}` groups, see [`crate::template::METRICS`].
+/// Git metrics are 0 outside a repository.
+pub fn metric_value(name: &str, d: &StatusInput, vcs: Option<&VcsInfo>) -> f64 {
+    let usage = d.context_window.current_usage.as_ref();
+    let git_lines = vcs
+        .map(|i| i.added + i.removed + i.staged_added + i.staged_removed)
+        .unwrap_or(0);
+    let git = |f: fn(&VcsInfo) -> u64| vcs.map(f).unwrap_or(0) as f64;
+    match name {
+        "cache_read" => usage.map_or(0, |u| u.cache_read_input_tokens) as f64,
+        "cache_write" => usage.map_or(0, |u| u.cache_creation_input_tokens) as f64,
+        "cost" => d.cost.total_cost_usd,
+        "ctx" => d.context_window.used_percentage,
+        "duration" => (d.cost.total_duration_ms / 1000) as f64,
+        "lines_added" => d.cost.total_lines_added as f64,
+        "lines_removed" => d.cost.total_lines_removed as f64,
+        "git_lines" => git_lines as f64,
+        "untracked" => git(|i| i.untracked),
+        "conflicts" => git(|i| i.conflicts),
+        "ahead" => git(|i| i.upstream.as_ref().map_or(0, |u| u.ahead)),
+        "behind" => git(|i| i.upstream.as_ref().map_or(0, |u| u.behind)),
+        "stack" => git(|i| i.stack.as_ref().map_or(0, |s| s.depth)),
+        "dirty" => (git_lines + vcs.map_or(0, |i| i.untracked + i.conflicts)) as f64,
+        _ => 0.0,
+    }
+}
+
+/// Appends rendered tokens to the current (last) segment, starting a new
+/// segment at each separator.
+fn render_tokens(
+    tokens: &[Token],
+    value: &dyn Fn(&Placeholder) -> String,
+    metric: &dyn Fn(&str) -> f64,
+    segments: &mut Vec<Segment>,
+) {
+    for token in tokens {
+        let segment = segments.last_mut().unwrap();
+        match token {
+            Token::Literal(text) => segment.text.push_str(text),
+            Token::Placeholder(p) if p.hidden => {}
+            Token::Placeholder(p) => {
+                let v = value(p);
+                let v = match &p.fallback {
+                    Some(fallback) if visible_text(&v).trim().is_empty() => fallback.clone(),
+                    _ => v,
+                };
+                if segment.lead.is_none() && !visible_text(&v).trim().is_empty() {
+                    segment.lead = Some(p.name.clone());
+                }
+                segment.text.push_str(&v);
+            }
+            Token::Separator => segments.push(Segment::default()),
+            // Only found at the top level, where lines are split beforehand.
+            Token::LineBreak | Token::Fill => {}
+            Token::Group(group) => {
+                let shown = match &group.predicate {
+                    Some(predicate) => predicate.holds(metric(&predicate.metric)),
+                    None => group.tokens.iter().all(|t| match t {
+                        Token::Placeholder(p) => {
+                            !p.hidden
+                                && (p.fallback.is_some()
+                                    || !visible_text(&value(p)).trim().is_empty())
+                        }
+                        _ => true,
+                    }),
+                };
+                if shown {
+                    render_tokens(&group.tokens, value, metric, segments);
+                }
+            }
+        }
+    }
+}
+
+/// Renders a parsed `format`; see [`crate::template::parse_format`].
 pub fn render_formatted(
-    fmt: &str,
+    tokens: &[Token],
     d: &StatusInput,
     cfg: &Config,
     col: &Colors,
@@ -243,15 +918,20 @@ pub fn render_formatted(
     light: bool,
     color_enabled: bool,
 ) -> String {
-    let bg = if cfg.enable_background && color_enabled {
-        Some(Rgb(bg_rgb.0, bg_rgb.1, bg_rgb.2))
-    } else {
-        None
+    let bg = background(cfg, bg_rgb, color_enabled);
+    let lookup = get_git_info_with_timeout(&d.cwd, cfg);
+    let values = build_replacements(d, cfg, col, bg_rgb, light, color_enabled, &lookup);
+    let value = |p: &Placeholder| {
+        render_with_option(p, d, cfg, col, bg, color_enabled)
+            .or_else(|| {
+                values
+                    .iter()
+                    .find(|(k, _)| *k == p.name)
+                    .map(|(_, v)| v.clone())
+            })
+            .unwrap_or_default()
     };
-    let mut s = fmt.to_string();
-    for (k, v) in build_replacements(d, cfg, col, bg, bg_rgb, light, color_enabled) {
-        s = s.replace(&format!("{{{}}}", k), &v);
-    }
+    let metric = |name: &str| metric_value(name, d, lookup.info());
     let sep_str = cfg
         .visual
         .as_ref()
@@ -266,9 +946,98 @@ pub fn render_formatted(
     } else {
         sep_str.clone()
     };
-    s.split('|')
-        .map(|s| s.trim())
-        .filter(|s| !s.is_empty())
+    let default_bg = rgb(bg_rgb.0, bg_rgb.1, bg_rgb.2);
+    let segment_bg = |lead: Option<&str>| {
+        lead.and_then(color_key)
+            .and_then(|key| col.backgrounds.get(key))
+            .and_then(parse_color)
+            .map_or(default_bg, |(r, g, b)| rgb(r, g, b))
+    };
+    let powerline = color_enabled && cfg.segment_style != SegmentStyle::Plain;
+    let render_line = |line: &[Token], right: bool| {
+        let mut segments = vec![Segment::default()];
+        render_tokens(line, &value, &metric, &mut segments);
+        let segments = segments
+            .iter()
+            .map(|s| (s.text.trim(), s.lead.as_deref()))
+            .filter(|(text, _)| !text.is_empty());
+        if powerline {
+            let blocks: Vec<(String, Color)> = segments
+                .map(|(text, lead)| (text.to_string(), segment_bg(lead)))
+                .collect();
+            let separator = color_from_spec(&col.separator);
+            join_powerline(&blocks, cfg.segment_style, separator, right)
+        } else {
+            segments
+                .map(|(text, _)| text)
+                .collect::<Vec<_>>()
+                .join(&joiner)
+        }
+    };
+    // Left and right of `{fill}`, if the line has one.
+    let render_parts = |line: &[Token]| match line.iter().position(|t| *t == Token::Fill) {
+        Some(i) => (
+            render_line(&line[..i], false),
+            Some(render_line(&line[i + 1..], true)),
+        ),
+        None => (render_line(line, false), None),
+    };
+    // Narrowest the line can be: right-aligned parts may touch.
+    let min_width = |(left, right): &(String, Option<String>)| {
+        display_width(left) + right.as_ref().map_or(0, |r| display_width(r))
+    };
+    let padding = |n: usize| {
+        let spaces = " ".repeat(n);
+        match bg {
+            Some(b) => Style::new().on(b).paint(spaces).to_string(),
+            None => spaces,
+        }
+    };
+    // Steps are validated at startup; invalid ones are skipped here.
+    let steps: Vec<ShrinkStep> = cfg
+        .shrink_order
+        .iter()
+        .filter_map(|step| parse_shrink_step(step).ok())
+        .collect();
+    // Lines are rendered independently, so separators and backgrounds never
+    // span a line break.
+    tokens
+        .split(|t| matches!(t, Token::LineBreak))
+        .map(|line| {
+            let width = cfg.max_width.filter(|w| *w > 0);
+            let mut parts = render_parts(line);
+            if let Some(width) = width {
+                let mut line = line.to_vec();
+                for step in &steps {
+                    if min_width(&parts) <= width {
+                        break;
+                    }
+                    if apply_shrink_step(&mut line, step) {
+                        parts = render_parts(&line);
+                    }
+                }
+            }
+            let (left, right) = parts;
+            let rendered = match (right.filter(|r| !r.is_empty()), width) {
+                (None, _) => left,
+                (Some(right), Some(width))
+                    if display_width(&left) + display_width(&right) <= width =>
+                {
+                    let gap = width - display_width(&left) - display_width(&right);
+                    format!("{}{}{}", left, padding(gap), right)
+                }
+                // Without a width to pad to, fall back to a plain separator.
+                (Some(right), _) if left.is_empty() => right,
+                // Powerline blocks already end and start with their own glyphs.
+                (Some(right), _) if powerline => format!("{}{}", left, right),
+                (Some(right), _) => format!("{}{}{}", left, joiner, right),
+            };
+            match width {
+                Some(width) => truncate_to_width(&rendered, width),
+                None => rendered,
+            }
+        })
+        .filter(|line| !line.is_empty())
         .collect::<Vec<_>>()
-        .join(&joiner)
+        .join("\n")
 }
@@ -0,0 +1,409 @@
+//! Parser for the `format` string.
+//!
+//! A format is literal text with `{placeholder}` or `{placeholder:option}`
+//! references, split into segments by `|` and into lines by newlines. `{fill}`
+//! right-aligns the rest of its line. `{placeholder?text}` shows `text`
+//! when the placeholder renders nothing. `{[...]}` groups are only rendered
+//! when every placeholder directly inside them is non-empty, and
+//! `{[?predicate ...]}` groups when the predicate holds, e.g.
+//! `{[?cache_read>50k {cache}]}`. Bare brackets are literal text.
+//!
+//! Braces are escaped by doubling them (`{{`, `}}`) and `\{`, `\}`, `\[`,
+//! `\]`, `\|` and `\\` produce the character itself.
+
+/// Every placeholder the renderer knows about.
+pub const PLACEHOLDERS: [&str; 25] = [
+    "head",
+    "tail",
+    "model",
+    "cwd",
+    "cwdcompact",
+    "duration",
+    "ctx",
+    "claudedelta",
+    "gitdelta",
+    "branch",
+    "upstream",
+    "stack",
+    "gitstate",
+    "bug",
+    "revision",
+    "searchfox",
+    "mozconfig",
+    "cost",
+    "cache",
+    "profile",
+    "session",
+    "ccversion",
+    "outputstyle",
+    "apiduration",
+    "projectdir",
+];
+
+/// Numbers that `{[?predicate ...]}` groups can test.
+pub const METRICS: [&str; 14] = [
+    "cache_read",
+    "cache_write",
+    "cost",
+    "ctx",
+    "duration",
+    "lines_added",
+    "lines_removed",
+    "git_lines",
+    "untracked",
+    "conflicts",
+    "ahead",
+    "behind",
+    "stack",
+    "dirty",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Literal(String),
+    Placeholder(Placeholder),
+    /// An unescaped `|`.
+    Separator,
+    /// A newline, starting another line of the status line.
+    LineBreak,
+    /// `{fill}`: what follows is pushed to the right edge of the line.
+    Fill,
+    /// `{[...]}`, or `{[?condition ...]}`.
+    Group(Group),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Placeholder {
+    pub name: String,
+    pub option: Option<PlaceholderOption>,
+    /// Shown instead of an empty value.
+    pub fallback: Option<String>,
+    /// Dropped by a [`ShrinkStep`] to make the line fit.
+    pub hidden: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Group {
+    /// Without one, the group needs all its placeholders to be non-empty.
+    pub predicate: Option<Predicate>,
+    pub tokens: Vec<Token>,
+}
+
+/// `metric`, `metric>N`, `metric>=N`, `metric<N`, `metric<=N`, `metric==N` or
+/// `metric!=N`. A bare metric means `metric>0`. `N` may end in `k` or `M`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Predicate {
+    pub metric: String,
+    pub op: CompareOp,
+    pub value: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompareOp {
+    Greater,
+    GreaterOrEqual,
+    Less,
+    LessOrEqual,
+    Equal,
+    NotEqual,
+}
+
+impl Predicate {
+    pub fn holds(&self, actual: f64) -> bool {
+        match self.op {
+            CompareOp::Greater => actual > self.value,
+            CompareOp::GreaterOrEqual => actual >= self.value,
+            CompareOp::Less => actual < self.value,
+            CompareOp::LessOrEqual => actual <= self.value,
+            CompareOp::Equal => actual == self.value,
+            CompareOp::NotEqual => actual != self.value,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PlaceholderOption {
+    /// `{cwd:compact}`, `{projectdir:compact}`: abbreviate parent directories.
+    Compact,
+    /// `{cwd:full}`, `{projectdir:full}`: show the path as is.
+    Full,
+    /// `{cost:.4}`: digits after the decimal point.
+    Precision(usize),
+    /// `{session:12}`: number of characters kept.
+    Length(usize),
+}
+
+fn parse_option(name: &str, option: &str) -> Option<PlaceholderOption> {
+    match (name, option) {
+        ("cwd" | "projectdir", "compact") => Some(PlaceholderOption::Compact),
+        ("cwd" | "projectdir", "full") => Some(PlaceholderOption::Full),
+        ("cost", o) => o
+            .strip_prefix('.')
+            .and_then(|p| p.parse().ok())
+            .map(PlaceholderOption::Precision),
+        ("session", o) => o.parse().ok().map(PlaceholderOption::Length),
+        _ => None,
+    }
+}
+
+fn parse_placeholder(inner: &str) -> Result<Placeholder, String> {
+    let (inner, fallback) = match inner.split_once('?') {
+        Some((inner, fallback)) => (inner, Some(fallback.to_string())),
+        None => (inner, None),
+    };
+    let (name, option) = match inner.split_once(':') {
+        Some((name, option)) => (name.trim(), Some(option.trim())),
+        None => (inner.trim(), None),
+    };
+    if !PLACEHOLDERS.contains(&name) {
+        return Err(format!("unknown placeholder {{{}}} in format", name));
+    }
+    let option = option
+        .map(|o| {
+            parse_option(name, o)
+                .ok_or_else(|| format!("invalid option '{}' for placeholder {{{}}}", o, name))
+        })
+        .transpose()?;
+    Ok(Placeholder {
+        name: name.to_string(),
+        option,
+        fallback,
+        hidden: false,
+    })
+}
+
+/// One entry of `shrink_order`: `name:option` switches a placeholder to that
+/// option, a bare `name` drops it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShrinkStep {
+    pub name: String,
+    pub option: Option<PlaceholderOption>,
+}
+
+pub fn parse_shrink_step(step: &str) -> Result<ShrinkStep, String> {
+    let placeholder =
+        parse_placeholder(step).map_err(|e| e.replace("in format", "in shrink_order"))?;
+    if placeholder.fallback.is_some() {
+        return Err(format!(
+            "unexpected fallback in shrink_order step '{}'",
+            step
+        ));
+    }
+    Ok(ShrinkStep {
+        name: placeholder.name,
+        option: placeholder.option,
+    })
+}
+
+/// Applies a shrink step to every matching placeholder, including those in
+/// groups. Returns whether anything changed.
+pub fn apply_shrink_step(tokens: &mut [Token], step: &ShrinkStep) -> bool {
+    let mut changed = false;
+    for token in tokens {
+        match token {
+            Token::Placeholder(p) if p.name == step.name && !p.hidden => match step.option {
+                Some(option) if p.option != Some(option) => {
+                    p.option = Some(option);
+                    changed = true;
+                }
+                Some(_) => {}
+                None => {
+                    p.hidden = true;
+                    changed = true;
+                }
+            },
+            Token::Group(group) => changed |= apply_shrink_step(&mut group.tokens, step),
+            _ => {}
+        }
+    }
+    changed
+}
+
+fn parse_number(text: &str) -> Option<f64> {
+    let (digits, scale) = match text.as_bytes().last()? {
+        b'k' | b'K' => (&text[..text.len() - 1], 1_000.0),
+        b'M' => (&text[..text.len() - 1], 1_000_000.0),
+        _ => (text, 1.0),
+    };
+    digits.parse::<f64>().ok().map(|n| n * scale)
+}
+
+pub fn parse_predicate(text: &str) -> Result<Predicate, String> {
+    const OPS: [(&str, CompareOp); 6] = [
+        (">=", CompareOp::GreaterOrEqual),
+        ("<=", CompareOp::LessOrEqual),
+        ("==", CompareOp::Equal),
+        ("!=", CompareOp::NotEqual),
+        (">", CompareOp::Greater),
+        ("<", CompareOp::Less),
+    ];
+    let (metric, op, value) = match text.find(['>', '<', '=', '!']) {
+        Some(i) => {
+            let (metric, rest) = text.split_at(i);
+            let (sym, op) = OPS
+                .iter()
+                .find(|(sym, _)| rest.starts_with(sym))
+                .ok_or_else(|| format!("invalid condition '{}' in format", text))?;
+            let value = parse_number(&rest[sym.len()..])
+                .ok_or_else(|| format!("invalid number in condition '{}' in format", text))?;
+            (metric, *op, value)
+        }
+        None => (text, CompareOp::Greater, 0.0),
+    };
+    if !METRICS.contains(&metric) {
+        return Err(format!("unknown metric '{}' in format condition", metric));
+    }
+    Ok(Predicate {
+        metric: metric.to_string(),
+        op,
+        value,
+    })
+}
+
+struct Parser<'a> {
+    fmt: &'a str,
+    chars: std::iter::Peekable<std::str::CharIndices<'a>>,
+}
+
+impl Parser<'_> {
+    fn column(&self, pos: usize) -> usize {
+        self.fmt[..pos].chars().count() + 1
+    }
+
+    /// Parses tokens up to the end of the format, or up to the `]}` closing
+    /// the group opened at `group_start`.
+    fn parse(&mut self, group_start: Option<usize>) -> Result<Vec<Token>, String> {
+        let mut tokens = Vec::new();
+        let mut literal = String::new();
+        let flush = |tokens: &mut Vec<Token>, literal: &mut String| {
+            if !literal.is_empty() {
+                tokens.push(Token::Literal(std::mem::take(literal)));
+            }
+        };
+        while let Some((pos, c)) = self.chars.next() {
+            match c {
+                '\\' => match self.chars.peek() {
+                    Some(&(_, e @ ('{' | '}' | '[' | ']' | '|' | '\\'))) => {
+                        self.chars.next();
+                        literal.push(e);
+                    }
+                    // Anything else is kept, so Windows paths need no escaping.
+                    _ => literal.push('\\'),
+                },
+                '{' | '}' if self.chars.peek().map(|&(_, n)| n) == Some(c) => {
+                    self.chars.next();
+                    literal.push(c);
+                }
+                '{' if self.chars.next_if(|&(_, n)| n == '[').is_some() => {
+                    flush(&mut tokens, &mut literal);
+                    let predicate = if self.chars.next_if(|&(_, n)| n == '?').is_some() {
+                        let mut text = String::new();
+                        while let Some((_, n)) =
+                            self.chars.next_if(|&(_, n)| !n.is_whitespace() && n != ']')
+                        {
+                            text.push(n);
+                        }
+                        // One space separates the condition from the contents.
+                        self.chars.next_if(|&(_, n)| n == ' ');
+                        Some(parse_predicate(&text)?)
+                    } else {
+                        None
+                    };
+                    let group = self.parse(Some(pos))?;
+                    tokens.push(Token::Group(Group {
+                        predicate,
+                        tokens: group,
+                    }));
+                }
+                '{' => {
+                    let len = self.fmt[pos..].find('}').ok_or_else(|| {
+                        format!("unclosed '{{' at column {} of format", self.column(pos))
+                    })?;
+                    let inner = &self.fmt[pos + 1..pos + len];
+                    let token = if inner.trim() == "fill" {
+                        if group_start.is_some() {
+                            return Err(format!(
+                                "{{fill}} inside '{{[' at column {} of format",
+                                self.column(pos)
+                            ));
+                        }
+                        let line_start = tokens
+                            .iter()
+                            .rposition(|t| matches!(t, Token::LineBreak))
+                            .map_or(0, |i| i + 1);
+                        if tokens[line_start..].contains(&Token::Fill) {
+                            return Err(format!(
+                                "second {{fill}} on one line at column {} of format",
+                                self.column(pos)
+                            ));
+                        }
+                        Token::Fill
+                    } else {
+                        Token::Placeholder(parse_placeholder(inner)?)
+                    };
+                    while self.chars.next_if(|&(i, _)| i <= pos + len).is_some() {}
+                    flush(&mut tokens, &mut literal);
+                    tokens.push(token);
+                }
+                '}' => {
+                    return Err(format!(
+                        "unmatched '}}' at column {} of format",
+                        self.column(pos)
+                    ))
+                }
+                ']' if group_start.is_some()
+                    && self.chars.next_if(|&(_, n)| n == '}').is_some() =>
+                {
+                    flush(&mut tokens, &mut literal);
+                    return Ok(tokens);
+                }
+                '|' => {
+                    flush(&mut tokens, &mut literal);
+                    tokens.push(Token::Separator);
+                }
+                '\r' if self.chars.peek().map(|&(_, n)| n) == Some('\n') => {}
+                '\n' if group_start.is_some() => {
+                    return Err(format!(
+                        "line break inside '{{[' at column {} of format",
+                        self.column(pos)
+                    ))
+                }
+                '\n' => {
+                    flush(&mut tokens, &mut literal);
+                    tokens.push(Token::LineBreak);
+                }
+                _ => literal.push(c),
+            }
+        }
+        if let Some(start) = group_start {
+            return Err(format!(
+                "unclosed '{{[' at column {} of format",
+                self.column(start)
+            ));
+        }
+        flush(&mut tokens, &mut literal);
+        Ok(tokens)
+    }
+}
+
+/// Whether the placeholder or metric `name` appears anywhere in `tokens`,
+/// including inside groups and their conditions.
+pub fn uses(tokens: &[Token], name: &str) -> bool {
+    tokens.iter().any(|token| match token {
+        Token::Placeholder(p) => p.name == name,
+        Token::Group(group) => {
+            group.predicate.as_ref().is_some_and(|p| p.metric == name) || uses(&group.tokens, name)
+        }
+        _ => false,
+    })
+}
+
+/// Tokenizes a format string, rejecting unknown placeholders, options and
+/// metrics.
+pub fn parse_format(fmt: &str) -> Result<Vec<Token>, String> {
+    Parser {
+        fmt,
+        chars: fmt.char_indices().peekable(),
+    }
+    .parse(None)
+}
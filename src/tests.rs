@@ -1,5 +1,6 @@
 use super::*;
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 
 #[test]
 fn test_abbreviate_path_simple() {
@@ -16,25 +17,36 @@ fn test_hsl_percent_parsing() {
 #[test]
 fn test_warning_message_customization_plain() {
     let d = StatusInput {
+        hook_event_name: None,
+        session_id: None,
+        transcript_path: None,
         cwd: "/tmp".to_string(),
         model: Model {
+            id: None,
             display_name: "M".to_string(),
         },
         workspace: Workspace {
             current_dir: "/tmp".to_string(),
+            project_dir: None,
         },
+        version: None,
+        output_style: None,
         cost: Cost {
             total_cost_usd: 0.0,
             total_duration_ms: 30_000,
+            total_api_duration_ms: None,
             total_lines_added: 0,
             total_lines_removed: 0,
         },
         context_window: ContextWindow {
+            total_input_tokens: None,
+            total_output_tokens: None,
             context_window_size: 200_000,
             used_percentage: 42.0,
             remaining_percentage: 58.0,
             current_usage: None,
         },
+        exceeds_200k_tokens: None,
     };
     let cfg = Config {
         warning_message: Some("warn {used_pct}% {used_k}k/{total_k}k".to_string()),
@@ -44,6 +56,93 @@ fn test_warning_message_customization_plain() {
     assert!(out.contains("warn 42.0% 84k/200k"));
 }
 
+const FULL_INPUT: &str = r#"{
+  "hook_event_name": "Status",
+  "session_id": "abcdef0123456789",
+  "transcript_path": "/tmp/transcript.json",
+  "cwd": "/tmp",
+  "model": {"id": "claude-opus-4-1", "display_name": "Opus"},
+  "workspace": {"current_dir": "/tmp", "project_dir": "/home/user/project"},
+  "version": "1.0.80",
+  "output_style": {"name": "Explanatory"},
+  "cost": {
+    "total_cost_usd": 0.5,
+    "total_duration_ms": 120000,
+    "total_api_duration_ms": 65000,
+    "total_lines_added": 3,
+    "total_lines_removed": 1
+  },
+  "context_window": {
+    "total_input_tokens": 1000,
+    "total_output_tokens": 500,
+    "context_window_size": 200000,
+    "used_percentage": 10.0,
+    "remaining_percentage": 90.0,
+    "current_usage": null
+  },
+  "exceeds_200k_tokens": false
+}"#;
+
+#[test]
+fn test_full_schema_placeholders() {
+    let d: StatusInput = serde_json::from_str(FULL_INPUT).unwrap();
+    assert_eq!(d.model.id.as_deref(), Some("claude-opus-4-1"));
+    assert_eq!(d.exceeds_200k_tokens, Some(false));
+    let cfg = Config {
+        enable_git: false,
+        ..Default::default()
+    };
+    let out = render_formatted(
+        &parse_format("{session} | {ccversion} | {outputstyle} | {apiduration} | {projectdir}")
+            .unwrap(),
+        &d,
+        &cfg,
+        &Colors::default(),
+        (0, 0, 0),
+        false,
+        false,
+    );
+    assert_eq!(
+        out,
+        "session:abcdef01 | cc:1.0.80 | style:Explanatory | api:1m | project:/h/u/project"
+    );
+}
+
+#[test]
+fn test_parse_status_input_tolerates_missing_and_unknown_fields() {
+    let (d, err) =
+        parse_status_input(r#"{"model": {"display_name": "Opus"}, "brand_new_field": {"x": 1}}"#);
+    assert!(err.is_none());
+    assert_eq!(d.model.display_name, "Opus");
+    assert_eq!(d.cost.total_duration_ms, 0);
+    assert!(d.context_window.current_usage.is_none());
+}
+
+#[test]
+fn test_parse_status_input_drops_malformed_fields() {
+    let (d, err) = parse_status_input(
+        r#"{"model": {"display_name": "Opus"}, "cost": {"total_cost_usd": "lots", "total_lines_added": 7}, "version": 3}"#,
+    );
+    assert!(err.is_some());
+    assert_eq!(d.model.display_name, "Opus");
+    assert_eq!(d.cost.total_cost_usd, 0.0);
+    assert_eq!(d.cost.total_lines_added, 7);
+    assert!(d.version.is_none());
+}
+
+#[test]
+fn test_parse_status_input_not_json() {
+    let (d, err) = parse_status_input("not json at all");
+    assert!(err.is_some());
+    assert!(d.model.display_name.is_empty());
+}
+
+#[test]
+fn test_render_input_error_marker() {
+    let cfg = Config::default();
+    assert_eq!(render_input_error(&cfg, (0, 0, 0), false, false), " [?]");
+}
+
 #[test]
 fn test_abbreviate_path_hidden_dirs() {
     assert_eq!(abbreviate_path("/home/user/.config/app"), "/h/u/.c/app");
@@ -98,11 +197,26 @@ fn test_get_symbol_with_emojis() {
         threshold_green: 100,
         threshold_yellow: 500,
         threshold_orange: 1000,
+        theme: None,
         colors: None,
         visual: None,
         format: default_format(),
         light_background: None,
+        detect_background: BackgroundDetection::Colorfgbg,
         warning_message: None,
+        git_delta_parts: default_git_delta_parts(),
+        git_timeout_ms: default_git_timeout_ms(),
+        git_cache_ttl_ms: default_git_cache_ttl_ms(),
+        enable_hyperlinks: false,
+        bug_url: default_bug_url(),
+        revision_url: default_revision_url(),
+        searchfox_url: default_searchfox_url(),
+        searchfox_tree: default_searchfox_tree(),
+        stack_bases: default_stack_bases(),
+        max_width: None,
+        shrink_order: default_shrink_order(),
+        segment_style: SegmentStyle::Plain,
+        min_contrast: default_min_contrast(),
     };
 
     assert_eq!(get_symbol("cwd", &config), "📁 ");
@@ -144,11 +258,26 @@ fn test_get_symbol_with_custom_overrides() {
         threshold_green: 100,
         threshold_yellow: 500,
         threshold_orange: 1000,
+        theme: None,
         colors: None,
         visual: None,
         format: default_format(),
         light_background: None,
+        detect_background: BackgroundDetection::Colorfgbg,
         warning_message: None,
+        git_delta_parts: default_git_delta_parts(),
+        git_timeout_ms: default_git_timeout_ms(),
+        git_cache_ttl_ms: default_git_cache_ttl_ms(),
+        enable_hyperlinks: false,
+        bug_url: default_bug_url(),
+        revision_url: default_revision_url(),
+        searchfox_url: default_searchfox_url(),
+        searchfox_tree: default_searchfox_tree(),
+        stack_bases: default_stack_bases(),
+        max_width: None,
+        shrink_order: default_shrink_order(),
+        segment_style: SegmentStyle::Plain,
+        min_contrast: default_min_contrast(),
     };
 
     assert_eq!(get_symbol("cwd", &config), "DIR:");
@@ -205,3 +334,1321 @@ fn test_config_default() {
     assert_eq!(config.warn_high_initial_context_threshold, 20.0);
     assert!(config.symbols.is_empty());
 }
+
+#[test]
+fn test_parse_stat_summary() {
+    let stat = " src/main.rs | 10 +++++++---\n src/git.rs | 6 +++---\n 2 files changed, 10 insertions(+), 6 deletions(-)\n";
+    assert_eq!(parse_stat_summary(stat), (10, 6));
+    assert_eq!(
+        parse_stat_summary(" 1 file changed, 1 insertion(+)\n"),
+        (1, 0)
+    );
+    assert_eq!(parse_stat_summary(""), (0, 0));
+}
+
+#[test]
+fn test_jj_label() {
+    assert_eq!(
+        jj_label("kxqzmlvo\n", Some("main,feature\n")).as_deref(),
+        Some("kxqzmlvo main")
+    );
+    assert_eq!(
+        jj_label("kxqzmlvo", Some("\n")).as_deref(),
+        Some("kxqzmlvo")
+    );
+    assert_eq!(jj_label("", None), None);
+}
+
+#[test]
+fn test_hg_label() {
+    assert_eq!(
+        hg_label("my-bookmark\n", Some("topic")).as_deref(),
+        Some("my-bookmark")
+    );
+    assert_eq!(
+        hg_label("", Some("webaudio-fix\n")).as_deref(),
+        Some("webaudio-fix")
+    );
+    assert_eq!(hg_label("", None), None);
+}
+
+#[test]
+fn test_parse_porcelain_v2_branch_and_upstream() {
+    let out = "# branch.oid 1234567890abcdef\n# branch.head main\n# branch.upstream origin/main\n# branch.ab +2 -1\n1 .M N... 100644 100644 100644 abc def src/main.rs\n";
+    let status = parse_porcelain_v2(out);
+    assert_eq!(status.branch.as_deref(), Some("main"));
+    assert_eq!(
+        status.upstream,
+        Some(Upstream {
+            name: "origin/main".to_string(),
+            ahead: 2,
+            behind: 1,
+        })
+    );
+    assert!(status.unstaged);
+    assert!(!status.staged);
+}
+
+#[test]
+fn test_parse_porcelain_v2_detached() {
+    let status = parse_porcelain_v2("# branch.oid 1234567890abcdef\n# branch.head (detached)\n");
+    assert_eq!(status.branch.as_deref(), Some("1234567"));
+    assert!(status.upstream.is_none());
+    assert!(!status.unstaged);
+}
+
+#[test]
+fn test_branch_and_upstream_placeholders() {
+    let info = VcsInfo {
+        branch: Some("main".to_string()),
+        upstream: Some(Upstream {
+            name: "origin/main".to_string(),
+            ahead: 3,
+            behind: 0,
+        }),
+        ..Default::default()
+    };
+    let cfg = Config::default();
+    let col = ColorSpec::Rgb(vec![0, 0, 0]);
+    assert_eq!(render_branch(Some(&info), &cfg, &col, None, false), "main");
+    assert_eq!(
+        render_upstream(Some(&info), &cfg, &col, None, false),
+        "up:origin/main ↑3"
+    );
+    assert_eq!(render_upstream(None, &cfg, &col, None, false), "");
+}
+
+#[test]
+fn test_parse_porcelain_v2_staged_and_untracked() {
+    let out = "# branch.oid abc\n# branch.head main\n1 M. N... 100644 100644 100644 abc def staged.rs\n1 AM N... 000000 100644 100644 000 def both.rs\n? new.rs\n? newdir/\n";
+    let status = parse_porcelain_v2(out);
+    assert!(status.staged);
+    assert!(status.unstaged);
+    assert_eq!(status.untracked, 2);
+}
+
+#[test]
+fn test_parse_numstat() {
+    assert_eq!(
+        parse_numstat("3\t1\ta.rs\n-\t-\timg.png\n10\t0\tb.rs\n"),
+        (13, 1)
+    );
+}
+
+#[test]
+fn test_git_delta_parts() {
+    let info = VcsInfo {
+        added: 7,
+        removed: 3,
+        staged_added: 5,
+        staged_removed: 1,
+        untracked: 2,
+        ..Default::default()
+    };
+    let cfg = Config::default();
+    assert_eq!(
+        git_delta_text(&info, &cfg),
+        ("S:+5 -1 +7 -3".to_string(), 16)
+    );
+    let cfg = Config {
+        git_delta_parts: vec![
+            "staged".to_string(),
+            "unstaged".to_string(),
+            "untracked".to_string(),
+        ],
+        ..Default::default()
+    };
+    assert_eq!(
+        git_delta_text(&info, &cfg),
+        ("S:+5 -1 +7 -3 ?2".to_string(), 16)
+    );
+    let cfg = Config {
+        git_delta_parts: vec!["total".to_string()],
+        ..Default::default()
+    };
+    assert_eq!(git_delta_text(&info, &cfg), ("+12 -4".to_string(), 16));
+    let staged_only = VcsInfo {
+        staged_added: 4,
+        ..Default::default()
+    };
+    let cfg = Config::default();
+    assert_eq!(
+        git_delta_text(&staged_only, &cfg),
+        ("S:+4 -0".to_string(), 4)
+    );
+}
+
+#[test]
+fn test_count_lines() {
+    assert_eq!(count_lines(b"a\nb\nc\n", b"a\nB\nc\nd\n"), (2, 1));
+    assert_eq!(count_lines(b"", b"x\ny\n"), (2, 0));
+    assert_eq!(count_lines(b"bin\0ary", b"text\n"), (0, 0));
+}
+
+#[test]
+fn test_render_git_timed_out() {
+    let cfg = Config::default();
+    let col = ColorSpec::Rgb(vec![0, 0, 0]);
+    let out = render_git(
+        &VcsLookup::TimedOut(None),
+        &cfg,
+        &col,
+        None,
+        (0, 0, 0),
+        false,
+        false,
+    );
+    assert_eq!(out, "…");
+    let out = render_git(
+        &VcsLookup::Done(None),
+        &cfg,
+        &col,
+        None,
+        (0, 0, 0),
+        false,
+        false,
+    );
+    assert_eq!(out, "");
+}
+
+#[test]
+fn test_vcs_info_cache_roundtrip() {
+    let info = VcsInfo {
+        since_commit: chrono::Duration::minutes(42),
+        added: 3,
+        branch: Some("main".to_string()),
+        ..Default::default()
+    };
+    let json = serde_json::to_string(&info).unwrap();
+    let back: VcsInfo = serde_json::from_str(&json).unwrap();
+    assert_eq!(back.since_commit.num_minutes(), 42);
+    assert_eq!(back.added, 3);
+    assert_eq!(back.branch.as_deref(), Some("main"));
+}
+
+#[test]
+fn test_cache_key_tracks_git_metadata() {
+    let root = std::env::temp_dir().join(format!("foxtail-cache-key-{}", std::process::id()));
+    let sub = root.join("src");
+    std::fs::create_dir_all(root.join(".git")).unwrap();
+    std::fs::create_dir_all(&sub).unwrap();
+    std::fs::write(root.join(".git/HEAD"), "ref: refs/heads/main\n").unwrap();
+
+    let key = CacheKey::for_cwd(sub.to_str().unwrap(), true, false).unwrap();
+    assert_eq!(key.root, root);
+    assert!(key.stamps[0].is_none());
+    assert!(key.stamps[1].is_some());
+
+    std::fs::write(root.join(".git/index"), "").unwrap();
+    let changed = CacheKey::for_cwd(sub.to_str().unwrap(), true, false).unwrap();
+    assert_ne!(key, changed);
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_cache_key_uses_closest_repository() {
+    let outer = std::env::temp_dir().join(format!("foxtail-cache-nested-{}", std::process::id()));
+    let inner = outer.join("r1");
+    std::fs::create_dir_all(outer.join(".hg")).unwrap();
+    std::fs::create_dir_all(inner.join(".git")).unwrap();
+    std::fs::write(inner.join(".git/HEAD"), "ref: refs/heads/main\n").unwrap();
+
+    let key = CacheKey::for_cwd(inner.to_str().unwrap(), false, false).unwrap();
+    assert_eq!(key.root, inner);
+    assert_eq!(key.stamps.len(), 2);
+    let key = CacheKey::for_cwd(outer.to_str().unwrap(), false, false).unwrap();
+    assert_eq!(key.root, outer);
+
+    // Colocated jj: both the jj and the git metadata are stamped.
+    std::fs::create_dir_all(inner.join(".jj")).unwrap();
+    let key = CacheKey::for_cwd(inner.to_str().unwrap(), false, false).unwrap();
+    assert_eq!(key.root, inner);
+    assert_eq!(key.stamps.len(), 4);
+    assert!(key.stamps[3].is_some());
+    std::fs::remove_dir_all(&outer).unwrap();
+}
+
+/// Builds a git CLI invocation in `dir` with a fixed identity, for tests that
+/// need a real repository.
+fn git_command(dir: &Path, args: &[&str]) -> std::process::Command {
+    let mut command = std::process::Command::new("git");
+    command
+        .args(["-c", "user.name=Fox", "-c", "user.email=fox@example.com"])
+        .args([
+            "-c",
+            "init.defaultBranch=main",
+            "-c",
+            "commit.gpgsign=false",
+        ])
+        .args(args)
+        .current_dir(dir)
+        .env_remove("GIT_DIR")
+        .env_remove("GIT_WORK_TREE");
+    command
+}
+
+fn run_git(dir: &Path, args: &[&str]) -> String {
+    let output = git_command(dir, args).output().unwrap();
+    assert!(output.status.success(), "git {:?}: {:?}", args, output);
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn test_nearest_repository_wins() {
+    let outer = std::env::temp_dir().join(format!("foxtail-nested-jj-{}", std::process::id()));
+    let inner = outer.join("r1");
+    let src = inner.join("src");
+    std::fs::create_dir_all(outer.join(".jj")).unwrap();
+    std::fs::create_dir_all(&src).unwrap();
+    run_git(&inner, &["init", "-q"]);
+    std::fs::write(src.join("a.txt"), "a\n").unwrap();
+    run_git(&inner, &["add", "."]);
+    run_git(&inner, &["commit", "-q", "-m", "Bug 1234567 - Start"]);
+
+    let cwd = src.to_str().unwrap();
+    assert_eq!(find_repo(cwd), Some((Vcs::Git, inner.clone())));
+    assert_eq!(
+        find_repo(outer.to_str().unwrap()),
+        Some((Vcs::Jj, outer.clone()))
+    );
+    let info = git::get_git_info(cwd, &Config::default()).unwrap();
+    assert_eq!(info.branch.as_deref(), Some("main"));
+    assert_eq!(info.bug.as_deref(), Some("1234567"));
+
+    // Colocated: jj wins the tie, and git answers when jj cannot.
+    std::fs::create_dir_all(inner.join(".jj")).unwrap();
+    assert_eq!(find_repo(cwd), Some((Vcs::Jj, inner.clone())));
+    let info = git::get_git_info(cwd, &Config::default()).unwrap();
+    assert_eq!(info.branch.as_deref(), Some("main"));
+    std::fs::remove_dir_all(&outer).unwrap();
+}
+
+#[test]
+fn test_git_repository_inside_hg_checkout() {
+    let outer = std::env::temp_dir().join(format!("foxtail-nested-hg-{}", std::process::id()));
+    let inner = outer.join("r1");
+    std::fs::create_dir_all(outer.join(".hg")).unwrap();
+    std::fs::create_dir_all(&inner).unwrap();
+    run_git(&inner, &["init", "-q"]);
+    run_git(&inner, &["commit", "-q", "--allow-empty", "-m", "Start"]);
+
+    let cwd = inner.to_str().unwrap();
+    assert_eq!(find_repo(cwd), Some((Vcs::Git, inner.clone())));
+    let info = git::get_git_info(cwd, &Config::default()).unwrap();
+    assert_eq!(info.branch.as_deref(), Some("main"));
+
+    // A closer .hg wins, but git answers when hg cannot.
+    let sub = inner.join("sub");
+    std::fs::create_dir_all(sub.join(".hg")).unwrap();
+    let cwd = sub.to_str().unwrap();
+    assert_eq!(find_repo(cwd), Some((Vcs::Hg, sub.clone())));
+    let info = git::get_git_info(cwd, &Config::default()).unwrap();
+    assert_eq!(info.branch.as_deref(), Some("main"));
+    std::fs::remove_dir_all(&outer).unwrap();
+}
+
+/// Reads a repository both in process and through the git CLI fallback,
+/// checking that they agree.
+fn read_both_ways(cwd: &Path, config: &Config) -> VcsInfo {
+    let repo = gix::discover(cwd).unwrap();
+    let mut in_process = git::read_git_repo(&repo, config).unwrap();
+    let mut cli = git::get_git_info_cli(cwd.to_str().unwrap(), config).unwrap();
+    assert!(in_process.since_commit.num_seconds() < 60);
+    (in_process.since_commit, cli.since_commit) = Default::default();
+    assert_eq!(in_process, cli);
+    in_process
+}
+
+#[test]
+fn test_git_info_matches_git_cli() {
+    let root = std::env::temp_dir().join(format!("foxtail-git-info-{}", std::process::id()));
+    let (origin, work) = (root.join("origin"), root.join("work"));
+    std::fs::create_dir_all(&origin).unwrap();
+    run_git(&origin, &["init", "-q"]);
+    std::fs::write(origin.join("a.txt"), "1\n2\n3\n").unwrap();
+    run_git(&origin, &["add", "."]);
+    run_git(&origin, &["commit", "-q", "-m", "Start"]);
+    run_git(&root, &["clone", "-q", "origin", "work"]);
+    std::fs::write(origin.join("b.txt"), "b\n").unwrap();
+    run_git(&origin, &["add", "."]);
+    run_git(&origin, &["commit", "-q", "-m", "Upstream change"]);
+    run_git(&work, &["fetch", "-q"]);
+    for n in 4..6 {
+        std::fs::write(work.join("a.txt"), format!("1\n2\n3\n{}\n", n)).unwrap();
+        run_git(
+            &work,
+            &["commit", "-q", "-am", "Bug 1234567 - Local change"],
+        );
+    }
+
+    let config = Config {
+        git_delta_parts: vec!["staged".into(), "unstaged".into(), "untracked".into()],
+        ..Default::default()
+    };
+    std::fs::write(work.join("a.txt"), "1\n2\n3\n5\nstaged\n").unwrap();
+    run_git(&work, &["add", "a.txt"]);
+    std::fs::write(work.join("a.txt"), "2\n3\n5\nstaged\nx\ny\n").unwrap();
+    std::fs::create_dir_all(work.join("new")).unwrap();
+    std::fs::write(work.join("new/c.txt"), "c\n").unwrap();
+    std::fs::write(work.join("new/d.txt"), "d\n").unwrap();
+    std::fs::write(work.join("e.txt"), "e\n").unwrap();
+
+    let info = read_both_ways(&work, &config);
+    let numstat = |args: &[&str]| parse_numstat(&run_git(&work, args));
+    assert_eq!((info.added, info.removed), numstat(&["diff", "--numstat"]));
+    assert_eq!((info.added, info.removed), (2, 1));
+    assert_eq!(
+        (info.staged_added, info.staged_removed),
+        numstat(&["diff", "--cached", "--numstat"])
+    );
+    assert_eq!((info.staged_added, info.staged_removed), (1, 0));
+    // The new directory counts once, as in `git status`.
+    assert_eq!(info.untracked, 2);
+    assert_eq!(info.branch.as_deref(), Some("main"));
+    assert_eq!(info.bug.as_deref(), Some("1234567"));
+    let upstream = info.upstream.unwrap();
+    assert_eq!(upstream.name, "origin/main");
+    assert_eq!((upstream.ahead, upstream.behind), (2, 1));
+    assert_eq!((info.conflicts, info.operation), (0, None));
+
+    // Merging the upstream change into a conflicting edit of b.txt.
+    run_git(&work, &["reset", "-q", "--hard"]);
+    std::fs::remove_dir_all(work.join("new")).unwrap();
+    std::fs::remove_file(work.join("e.txt")).unwrap();
+    std::fs::write(work.join("b.txt"), "local\n").unwrap();
+    run_git(&work, &["add", "b.txt"]);
+    run_git(&work, &["commit", "-q", "-m", "Conflicting change"]);
+    let merge = git_command(&work, &["merge", "-q", "origin/main"]).output();
+    assert!(!merge.unwrap().status.success());
+    let info = read_both_ways(&work, &config);
+    assert_eq!(info.conflicts, 1);
+    assert_eq!(info.operation.as_deref(), Some("merge"));
+
+    run_git(&work, &["merge", "--abort"]);
+    run_git(&work, &["checkout", "-q", "--detach"]);
+    let info = read_both_ways(&work, &config);
+    let head = run_git(&work, &["rev-parse", "--short=7", "HEAD"]);
+    assert_eq!(info.branch.as_deref(), Some(head.trim()));
+    assert_eq!(info.upstream, None);
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_read_operation() {
+    let dir = std::env::temp_dir().join(format!("foxtail-git-op-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("rebase-merge")).unwrap();
+    std::fs::write(dir.join("rebase-merge/msgnum"), "3\n").unwrap();
+    std::fs::write(dir.join("rebase-merge/end"), "7\n").unwrap();
+    assert_eq!(read_operation(&dir).as_deref(), Some("rebase 3/7"));
+
+    std::fs::remove_dir_all(dir.join("rebase-merge")).unwrap();
+    assert_eq!(read_operation(&dir), None);
+    std::fs::write(dir.join("MERGE_HEAD"), "abc\n").unwrap();
+    assert_eq!(read_operation(&dir).as_deref(), Some("merge"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_git_state_placeholder() {
+    let cfg = Config::default();
+    let col = ColorSpec::Rgb(vec![0, 0, 0]);
+    let info = VcsInfo {
+        operation: Some("rebase 3/7".to_string()),
+        conflicts: 2,
+        ..Default::default()
+    };
+    assert_eq!(
+        render_git_state(Some(&info), &cfg, &col, None, false),
+        "REBASE 3/7 conflicts:2"
+    );
+    assert_eq!(
+        render_git_state(Some(&VcsInfo::default()), &cfg, &col, None, false),
+        ""
+    );
+    let status = parse_porcelain_v2("# branch.head main\nu UU N... 1 2 3 4 a b c f.rs\n");
+    assert_eq!(status.conflicts, 1);
+}
+
+#[test]
+fn test_find_bug_and_revision_from_message() {
+    let message = "Bug 1234567 - Fix the thing. r=padenot\n\nDifferential Revision: https://phabricator.services.mozilla.com/D123456\n";
+    assert_eq!(
+        find_bug_and_revision(message, Some("main")),
+        (Some("1234567".to_string()), Some("D123456".to_string()))
+    );
+}
+
+#[test]
+fn test_find_bug_and_revision_branch_fallback() {
+    assert_eq!(
+        find_bug_and_revision("WIP", Some("bug-1888888-webaudio")),
+        (Some("1888888".to_string()), None)
+    );
+    assert_eq!(
+        find_bug_and_revision("", Some("D98765-followup")),
+        (None, Some("D98765".to_string()))
+    );
+    // Not bug numbers: too short, or part of a word.
+    assert_eq!(
+        find_bug_and_revision("debug 12345 and bug 12", Some("ladybug-77777x")),
+        (None, None)
+    );
+}
+
+#[test]
+fn test_hyperlinks() {
+    let col = ColorSpec::Rgb(vec![0, 0, 0]);
+    let cfg = Config {
+        enable_hyperlinks: true,
+        ..Default::default()
+    };
+    let url = link_url(&cfg, &cfg.bug_url, "1234567");
+    assert_eq!(
+        url.as_deref(),
+        Some("https://bugzilla.mozilla.org/show_bug.cgi?id=1234567")
+    );
+    let out = paint_link(&col, "bug 1234567".to_string(), url.clone(), None, true);
+    assert!(out.contains("\x1b]8;;https://bugzilla.mozilla.org/show_bug.cgi?id=1234567"));
+    assert!(out.contains("bug 1234567"));
+    // No escape sequences at all without color.
+    assert_eq!(
+        paint_link(&col, "bug 1".to_string(), url, None, false),
+        "bug 1"
+    );
+    assert_eq!(link_url(&Config::default(), "x{id}", "1"), None);
+    assert_eq!(file_url("/home/me/my dir"), "file:///home/me/my%20dir");
+}
+
+#[test]
+fn test_searchfox() {
+    let root = std::env::temp_dir().join(format!("foxtail-sf-{}", std::process::id()));
+    let dir = root.join("dom/media/web audio");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(root.join("mach"), "").unwrap();
+    assert_eq!(find_checkout_root(dir.to_str().unwrap()), None);
+    std::fs::write(root.join("moz.configure"), "").unwrap();
+    assert_eq!(
+        find_checkout_root(dir.to_str().unwrap()),
+        Some(root.clone())
+    );
+
+    let rel = checkout_relative_path(&root, dir.to_str().unwrap()).unwrap();
+    assert_eq!(rel, "dom/media/web audio");
+    assert_eq!(
+        searchfox_url("https://searchfox.org/", "mozilla-central", &rel),
+        "https://searchfox.org/mozilla-central/source/dom/media/web%20audio"
+    );
+    assert_eq!(
+        checkout_relative_path(&root, root.to_str().unwrap()).unwrap(),
+        ""
+    );
+    assert_eq!(
+        searchfox_url("https://searchfox.org", "mozilla-central", ""),
+        "https://searchfox.org/mozilla-central/source"
+    );
+
+    let cfg = Config::default();
+    let col = ColorSpec::Rgb(vec![0, 0, 0]);
+    let out = render_searchfox(dir.to_str().unwrap(), &cfg, &col, None, false);
+    assert_eq!(out, "sf:dom/media/web audio");
+    assert_eq!(render_searchfox("/", &cfg, &col, None, false), "");
+    // Links are opt-in, like the other placeholders.
+    let colored = render_searchfox(dir.to_str().unwrap(), &cfg, &col, None, true);
+    assert!(!colored.contains("\x1b]8;"), "{:?}", colored);
+    let cfg = Config {
+        enable_hyperlinks: true,
+        ..Default::default()
+    };
+    let linked = render_searchfox(dir.to_str().unwrap(), &cfg, &col, None, true);
+    assert!(
+        linked
+            .contains("\x1b]8;;https://searchfox.org/mozilla-central/source/dom/media/web%20audio"),
+        "{:?}",
+        linked
+    );
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_mozconfig() {
+    assert_eq!(mozconfig_label(Path::new("/src/mozconfig")), "default");
+    assert_eq!(mozconfig_label(Path::new("/src/.mozconfig")), "default");
+    assert_eq!(mozconfig_label(Path::new("/src/mozconfig.debug")), "debug");
+    assert_eq!(mozconfig_label(Path::new("/home/me/configs/asan")), "asan");
+
+    let contents = "\
+. \"$topsrcdir/browser/config/mozconfig\"
+ac_add_options --enable-debug
+# mk_add_options MOZ_OBJDIR=@TOPSRCDIR@/obj-commented
+mk_add_options MOZ_OBJDIR=@TOPSRCDIR@/obj-opt
+mk_add_options MOZ_OBJDIR=\"@TOPSRCDIR@/obj-debug/\"  # trailing comment
+";
+    assert_eq!(
+        parse_mozconfig_objdir(contents),
+        Some("obj-debug".to_string())
+    );
+    assert_eq!(
+        parse_mozconfig_objdir("ac_add_options --with-objdir=/builds/obj-ff"),
+        Some("obj-ff".to_string())
+    );
+    assert_eq!(
+        parse_mozconfig_objdir("ac_add_options --enable-debug"),
+        None
+    );
+
+    let root = std::env::temp_dir().join(format!("foxtail-mc-{}", std::process::id()));
+    std::fs::create_dir_all(&root).unwrap();
+    assert_eq!(find_mozconfig(&root, None), None);
+    std::fs::write(root.join("mozconfig"), "").unwrap();
+    assert_eq!(find_mozconfig(&root, None), Some(root.join("mozconfig")));
+    assert_eq!(
+        find_mozconfig(&root, Some("")),
+        Some(root.join("mozconfig"))
+    );
+    assert_eq!(
+        find_mozconfig(&root, Some("mozconfig.asan")),
+        Some(root.join("mozconfig.asan"))
+    );
+    assert_eq!(
+        find_mozconfig(&root, Some("/abs/mozconfig")),
+        Some(PathBuf::from("/abs/mozconfig"))
+    );
+    std::fs::remove_dir_all(&root).unwrap();
+
+    let cfg = Config::default();
+    let col = ColorSpec::Rgb(vec![0, 0, 0]);
+    let m = Mozconfig {
+        label: "debug".to_string(),
+        objdir: Some("obj-debug".to_string()),
+    };
+    assert_eq!(
+        render_mozconfig(Some(&m), &cfg, &col, None, false),
+        "mc:debug→obj-debug"
+    );
+    assert_eq!(render_mozconfig(None, &cfg, &col, None, false), "");
+}
+
+#[test]
+fn test_render_stack() {
+    let cfg = Config::default();
+    let col = ColorSpec::Rgb(vec![0, 0, 0]);
+    let mut info = VcsInfo {
+        stack: Some(Stack {
+            depth: 5,
+            base_since: chrono::Duration::hours(50),
+        }),
+        ..Default::default()
+    };
+    assert_eq!(
+        render_stack(Some(&info), &cfg, &col, None, false),
+        "stack:5 (2d2h)"
+    );
+    info.stack.as_mut().unwrap().depth = 0;
+    assert_eq!(render_stack(Some(&info), &cfg, &col, None, false), "");
+    assert_eq!(render_stack(None, &cfg, &col, None, false), "");
+}
+
+#[test]
+fn test_parse_format_tokens() {
+    let tokens = parse_format("a{{b}} {cwd:compact}|\\| {cost:.4}").unwrap();
+    assert_eq!(
+        tokens,
+        vec![
+            Token::Literal("a{b} ".to_string()),
+            Token::Placeholder(Placeholder {
+                name: "cwd".to_string(),
+                option: Some(PlaceholderOption::Compact),
+                fallback: None,
+                hidden: false,
+            }),
+            Token::Separator,
+            Token::Literal("| ".to_string()),
+            Token::Placeholder(Placeholder {
+                name: "cost".to_string(),
+                option: Some(PlaceholderOption::Precision(4)),
+                fallback: None,
+                hidden: false,
+            }),
+        ]
+    );
+    // Backslashes that escape nothing are kept.
+    assert_eq!(
+        parse_format("C:\\dir").unwrap(),
+        vec![Token::Literal("C:\\dir".to_string())]
+    );
+}
+
+#[test]
+fn test_parse_format_errors() {
+    assert_eq!(
+        parse_format("{ctxx}").unwrap_err(),
+        "unknown placeholder {ctxx} in format"
+    );
+    assert_eq!(
+        parse_format("{cwd:tiny}").unwrap_err(),
+        "invalid option 'tiny' for placeholder {cwd}"
+    );
+    assert!(parse_format("{model:2}").is_err());
+    assert!(parse_format("ab {model").unwrap_err().contains("column 4"));
+    assert!(parse_format("}").is_err());
+    assert!(parse_format(&default_format()).is_ok());
+}
+
+#[test]
+fn test_placeholders_match_replacements() {
+    let d = StatusInput::default();
+    let cfg = Config {
+        enable_git: false,
+        ..Default::default()
+    };
+    let lookup = VcsLookup::Done(None);
+    let values = build_replacements(
+        &d,
+        &cfg,
+        &Colors::default(),
+        (0, 0, 0),
+        false,
+        false,
+        &lookup,
+    );
+    let names: Vec<&str> = values.iter().map(|(k, _)| *k).collect();
+    assert_eq!(names, PLACEHOLDERS);
+}
+
+#[test]
+fn test_render_formatted_options_and_pipes() {
+    let (d, _) = parse_status_input(
+        r#"{"model": {"display_name": "A|B"}, "session_id": "abcdef0123456789",
+            "workspace": {"current_dir": "/home/user/project"}, "cost": {"total_cost_usd": 0.5}}"#,
+    );
+    let cfg = Config {
+        enable_git: false,
+        ..Default::default()
+    };
+    let render = |fmt: &str| {
+        render_formatted(
+            &parse_format(fmt).unwrap(),
+            &d,
+            &cfg,
+            &Colors::default(),
+            (0, 0, 0),
+            false,
+            false,
+        )
+    };
+    assert_eq!(render("{model} | {cost:.4}"), "A|B | $0.5000");
+    assert_eq!(
+        render("{cwd:compact}|{session:4}"),
+        "cwd:/h/u/project | session:abcd"
+    );
+    assert_eq!(render("{{x\\|y}}"), "{x|y}");
+}
+
+#[test]
+fn test_parse_format_groups() {
+    let tokens = parse_format("{[?cache_read>=50k {cache}]}").unwrap();
+    let Token::Group(group) = &tokens[0] else {
+        panic!("expected a group: {:?}", tokens);
+    };
+    let predicate = group.predicate.as_ref().unwrap();
+    assert_eq!(predicate.metric, "cache_read");
+    assert_eq!(predicate.op, CompareOp::GreaterOrEqual);
+    assert_eq!(predicate.value, 50_000.0);
+    assert!(matches!(&group.tokens[0], Token::Placeholder(p) if p.name == "cache"));
+
+    assert!(parse_predicate("dirty").unwrap().holds(1.0));
+    assert!(!parse_predicate("dirty").unwrap().holds(0.0));
+    assert!(parse_predicate("bogus>1").is_err());
+    assert!(parse_predicate("cost>lots").is_err());
+    assert!(parse_format("{[{model}")
+        .unwrap_err()
+        .contains("unclosed '{['"));
+    // Bare brackets are literal, as they were before groups existed.
+    assert_eq!(
+        parse_format("[{model}]").unwrap(),
+        vec![
+            Token::Literal("[".to_string()),
+            Token::Placeholder(Placeholder {
+                name: "model".to_string(),
+                option: None,
+                fallback: None,
+                hidden: false,
+            }),
+            Token::Literal("]".to_string()),
+        ]
+    );
+    assert_eq!(
+        parse_format("\\[x\\]").unwrap(),
+        vec![Token::Literal("[x]".to_string())]
+    );
+    // A `]` not followed by `}` stays literal inside a group.
+    let tokens = parse_format("{[a]b]}").unwrap();
+    let Token::Group(group) = &tokens[0] else {
+        panic!("expected a group: {:?}", tokens);
+    };
+    assert_eq!(group.tokens, vec![Token::Literal("a]b".to_string())]);
+}
+
+#[test]
+fn test_render_formatted_groups_and_fallbacks() {
+    let (d, _) = parse_status_input(
+        r#"{"model": {"display_name": "Opus"}, "context_window": {"current_usage":
+            {"cache_read_input_tokens": 80000, "cache_creation_input_tokens": 1000}}}"#,
+    );
+    let cfg = Config {
+        enable_git: false,
+        ..Default::default()
+    };
+    let render = |fmt: &str| {
+        render_formatted(
+            &parse_format(fmt).unwrap(),
+            &d,
+            &cfg,
+            &Colors::default(),
+            (0, 0, 0),
+            false,
+            true,
+        )
+    };
+    let plain = |fmt: &str| visible_text(&render(fmt));
+    assert_eq!(plain("{model}{[ ({profile})]}"), "Opus");
+    assert_eq!(plain("{model}{[ ({ccversion?n/a})]}"), "Opus (n/a)");
+    assert_eq!(plain("{[?cache_read>50k {cache}]}"), "cache:r:80k w:1k");
+    assert_eq!(plain("{model}|{[?cache_read>100k {cache}]}"), "Opus");
+    assert_eq!(plain("{model}|{[?dirty {gitdelta}]}"), "Opus");
+    assert_eq!(plain("[{model}]"), "[Opus]");
+    assert_eq!(plain("{bug?no bug}"), "no bug");
+}
+
+#[test]
+fn test_visible_text() {
+    let col = ColorSpec::Rgb(vec![1, 2, 3]);
+    let out = paint_link(
+        &col,
+        "x".to_string(),
+        Some("https://e.x".to_string()),
+        None,
+        true,
+    );
+    assert_eq!(visible_text(&out), "x");
+    assert_eq!(
+        visible_text(&paint_link(&col, String::new(), None, None, true)),
+        ""
+    );
+}
+
+#[test]
+fn test_multi_line_format() {
+    let cfg: Config = toml::from_str(
+        r#"
+enable_git = false
+format = ["{model} | {cost}", "{profile}", "{cwd}"]
+"#,
+    )
+    .unwrap();
+    assert_eq!(cfg.format, "{model} | {cost}\n{profile}\n{cwd}");
+    let (d, _) = parse_status_input(
+        r#"{"model": {"display_name": "Opus"}, "workspace": {"current_dir": "/tmp"}}"#,
+    );
+    let out = render_formatted(
+        &parse_format(&cfg.format).unwrap(),
+        &d,
+        &cfg,
+        &Colors::default(),
+        (0, 0, 0),
+        false,
+        false,
+    );
+    // The empty {profile} line is dropped.
+    assert_eq!(out, "Opus | $0.00\ncwd:/tmp");
+
+    let cfg: Config = toml::from_str("format = \"{model}\\r\\n{cwd}\"").unwrap();
+    assert_eq!(
+        parse_format(&cfg.format).unwrap().len(),
+        3,
+        "CRLF is a single line break"
+    );
+    assert!(parse_format("{[{model}\n]}").is_err());
+}
+
+#[test]
+fn test_display_width_and_truncation() {
+    assert_eq!(display_width("abc"), 3);
+    assert_eq!(display_width("日本"), 4);
+    let col = ColorSpec::Rgb(vec![1, 2, 3]);
+    let painted = paint_link(&col, "hello world".to_string(), None, None, true);
+    assert_eq!(display_width(&painted), 11);
+
+    assert_eq!(truncate_to_width("hello world", 20), "hello world");
+    assert_eq!(truncate_to_width("hello world", 6), "hello…");
+    assert_eq!(truncate_to_width("日本語", 4), "日…");
+    let cut = truncate_to_width(&painted, 6);
+    assert_eq!(visible_text(&cut), "hello…");
+    assert!(cut.ends_with("\x1b[0m…"));
+    let url = Some("https://e.x".to_string());
+    let link = paint_link(&col, "hello world".to_string(), url, None, true);
+    let cut = truncate_to_width(&link, 6);
+    assert!(cut.ends_with("\x1b]8;;\x1b\\\x1b[0m…"), "{:?}", cut);
+}
+
+#[test]
+fn test_shrink_order() {
+    let (d, _) = parse_status_input(
+        r#"{"model": {"display_name": "Opus"}, "workspace": {"current_dir": "/home/user/project"},
+            "cost": {"total_cost_usd": 1.5}, "context_window": {"current_usage":
+            {"cache_read_input_tokens": 80000, "cache_creation_input_tokens": 1000}}}"#,
+    );
+    let render = |width: usize| {
+        let cfg = Config {
+            enable_git: false,
+            max_width: Some(width),
+            shrink_order: vec![
+                "cwd:compact".to_string(),
+                "cache".to_string(),
+                "cost".to_string(),
+            ],
+            ..Default::default()
+        };
+        render_formatted(
+            &parse_format("{model} | {cwd} | {cache} | {cost}").unwrap(),
+            &d,
+            &cfg,
+            &Colors::default(),
+            (0, 0, 0),
+            false,
+            false,
+        )
+    };
+    let full = "Opus | cwd:/home/user/project | cache:r:80k w:1k | $1.50";
+    assert_eq!(render(100), full);
+    assert_eq!(
+        render(50),
+        "Opus | cwd:/h/u/project | cache:r:80k w:1k | $1.50"
+    );
+    assert_eq!(render(31), "Opus | cwd:/h/u/project | $1.50");
+    assert_eq!(render(30), "Opus | cwd:/h/u/project");
+    assert_eq!(render(20), "Opus | cwd:/h/u/pro…");
+
+    assert_eq!(
+        parse_shrink_step("cwd:compact").unwrap(),
+        ShrinkStep {
+            name: "cwd".to_string(),
+            option: Some(PlaceholderOption::Compact),
+        }
+    );
+    assert!(parse_shrink_step("nope").is_err());
+}
+
+#[test]
+fn test_fill_right_alignment() {
+    let (d, _) = parse_status_input(
+        r#"{"model": {"display_name": "Opus"}, "cost": {"total_cost_usd": 1.5}}"#,
+    );
+    let render = |fmt: &str, width: Option<usize>| {
+        let cfg = Config {
+            enable_git: false,
+            max_width: width,
+            shrink_order: vec![],
+            ..Default::default()
+        };
+        render_formatted(
+            &parse_format(fmt).unwrap(),
+            &d,
+            &cfg,
+            &Colors::default(),
+            (0, 0, 0),
+            false,
+            false,
+        )
+    };
+    assert_eq!(
+        render("{model} {fill} {cost}", Some(16)),
+        "Opus       $1.50"
+    );
+    assert_eq!(render("{fill}{cost}", Some(8)), "   $1.50");
+    assert_eq!(render("{model} {fill} {cost}", None), "Opus | $1.50");
+    assert_eq!(render("{model} {fill} {cost}", Some(10)), "Opus $1.50");
+    // Exactly as wide as the line: the parts touch.
+    assert_eq!(render("{model} {fill} {cost}", Some(9)), "Opus$1.50");
+    // Too narrow to pad: separator, then truncation.
+    assert_eq!(render("{model} {fill} {cost}", Some(8)), "Opus | …");
+    assert_eq!(render("{model} | {fill} | {profile}", Some(16)), "Opus");
+
+    assert!(parse_format("{model}{fill}{cost}\n{fill}{cwd}").is_ok());
+    assert!(parse_format("{fill}{model}{fill}").is_err());
+    assert!(parse_format("{[{fill}]}").is_err());
+}
+
+#[test]
+fn test_powerline_segments() {
+    let (d, _) = parse_status_input(
+        r#"{"model": {"display_name": "Opus"}, "cost": {"total_cost_usd": 1.5}}"#,
+    );
+    let mut col = Colors::default();
+    col.backgrounds
+        .insert("model".to_string(), ColorSpec::Rgb(vec![10, 20, 30]));
+    let cfg = Config {
+        enable_git: false,
+        segment_style: SegmentStyle::Powerline,
+        ..Default::default()
+    };
+    let render = |fmt: &str, color_enabled: bool| {
+        render_formatted(
+            &parse_format(fmt).unwrap(),
+            &d,
+            &cfg,
+            &col,
+            (40, 50, 60),
+            false,
+            color_enabled,
+        )
+    };
+    let out = render("{model} | {cost} | {duration}", true);
+    assert_eq!(
+        visible_text(&out),
+        " Opus \u{e0b0} $1.50 \u{e0b1} 0s \u{e0b0}"
+    );
+    // Arrow from the model block into the default background.
+    assert!(
+        out.contains("\x1b[48;2;40;50;60;38;2;10;20;30m\u{e0b0}"),
+        "{:?}",
+        out
+    );
+    // The segment background survives the reset ending the painted value.
+    assert!(out.contains("\x1b[0m\x1b[48;2;10;20;30m"), "{:?}", out);
+    // Plain separators without color.
+    assert_eq!(render("{model} | {cost}", false), "Opus | $1.50");
+
+    let right = render("{model} {fill} {cost}", true);
+    assert_eq!(visible_text(&right), " Opus \u{e0b0}\u{e0b2} $1.50 ");
+}
+
+#[test]
+fn test_segment_style_config() {
+    let cfg: Config = toml::from_str(
+        r##"
+segment_style = "rounded"
+[colors.backgrounds]
+cwd = [32, 32, 32]
+"##,
+    )
+    .unwrap();
+    assert_eq!(cfg.segment_style, SegmentStyle::Rounded);
+    let colors = cfg.colors.unwrap();
+    assert!(colors.backgrounds.contains_key("cwd"));
+    assert_eq!(color_key("cwdcompact"), Some("cwd"));
+    assert_eq!(color_key("head"), None);
+}
+
+#[test]
+fn test_bundled_themes_are_complete() {
+    let keys: Vec<String> = toml::Table::try_from(Colors::default())
+        .unwrap()
+        .keys()
+        .cloned()
+        .collect();
+    for (name, _) in THEMES {
+        let table = load_theme(name, None).unwrap();
+        for key in &keys {
+            assert!(table.contains_key(key), "{} lacks {}", name, key);
+        }
+        let colors: Colors = table.try_into().unwrap();
+        assert_eq!(crate::colors::validate_colors(&colors), Ok(()), "{}", name);
+    }
+    assert!(load_theme("no-such-theme", None)
+        .unwrap_err()
+        .contains("fox-dark"));
+}
+
+#[test]
+fn test_theme_with_color_overrides() {
+    let cfg = parse_config(
+        r##"
+theme = "fox-dark"
+[colors]
+model = [1, 2, 3]
+[colors.backgrounds]
+cwd = "#202020"
+"##,
+        None,
+    )
+    .unwrap();
+    assert_eq!(cfg.theme.as_deref(), Some("fox-dark"));
+    let colors = cfg.colors.unwrap();
+    assert_eq!(parse_color(&colors.model), Some((1, 2, 3)));
+    // Entries not overridden come from the theme, not Colors::default().
+    assert_eq!(parse_color(&colors.cwd), Some((0xff, 0xcb, 0x6b)));
+    assert_eq!(parse_color(&colors.backgrounds["cwd"]), Some((32, 32, 32)));
+
+    // Without a theme, [colors] keeps filling in from the defaults.
+    let cfg = parse_config("[colors]\nmodel = [1, 2, 3]", None).unwrap();
+    assert_eq!(parse_color(&cfg.colors.unwrap().cwd), Some((0, 0, 0)));
+}
+
+#[test]
+fn test_theme_file() {
+    let dir = std::env::temp_dir().join(format!("foxtail-theme-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(
+        dir.join("mine.toml"),
+        "model = \"#123\"\n[backgrounds]\nmodel = [9, 9, 9]\n",
+    )
+    .unwrap();
+    let cfg = parse_config(
+        "theme = \"mine.toml\"\n[colors.backgrounds]\ncwd = [8, 8, 8]",
+        Some(&dir),
+    )
+    .unwrap();
+    let colors = cfg.colors.unwrap();
+    assert_eq!(parse_color(&colors.model), Some((0x11, 0x22, 0x33)));
+    assert_eq!(parse_color(&colors.backgrounds["model"]), Some((9, 9, 9)));
+    assert_eq!(parse_color(&colors.backgrounds["cwd"]), Some((8, 8, 8)));
+
+    // A missing theme file leaves [colors] alone; main reports the error.
+    let cfg = parse_config("theme = \"missing.toml\"", Some(&dir)).unwrap();
+    assert!(cfg.colors.is_none());
+    assert!(load_theme("missing.toml", Some(&dir)).is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_hex_color_strings() {
+    let colors: Colors = toml::from_str("model = \"#ff8000\"\ncwd = \"#f80\"").unwrap();
+    assert_eq!(parse_color(&colors.model), Some((255, 128, 0)));
+    assert_eq!(parse_color(&colors.cwd), Some((255, 136, 0)));
+    assert_eq!(parse_color(&ColorSpec::Hsl("#12".to_string())), None);
+    assert_eq!(parse_color(&ColorSpec::Hsl("#aébcd".to_string())), None);
+    assert_eq!(parse_color(&ColorSpec::Hex("#é".to_string())), None);
+}
+
+#[test]
+fn test_parse_colorfgbg() {
+    assert_eq!(parse_colorfgbg("15;0"), Some(false));
+    assert_eq!(parse_colorfgbg("0;15"), Some(true));
+    assert_eq!(parse_colorfgbg("0;default;7"), Some(true));
+    assert_eq!(parse_colorfgbg("7;8"), Some(false));
+    assert_eq!(parse_colorfgbg("0;default"), None);
+    assert_eq!(parse_colorfgbg("0;200"), None);
+}
+
+#[test]
+fn test_detect_background_is_opt_in() {
+    let cfg = parse_config("", None).unwrap();
+    assert_eq!(cfg.detect_background, BackgroundDetection::Colorfgbg);
+    let cfg = parse_config("detect_background = \"osc11\"", None).unwrap();
+    assert_eq!(cfg.detect_background, BackgroundDetection::Osc11);
+    assert!(parse_config("detect_background = \"tty\"", None).is_err());
+}
+
+#[test]
+fn test_parse_osc11_response() {
+    assert_eq!(
+        parse_osc11_response("\x1b]11;rgb:ffff/ffff/dddd\x07\x1b[?62;22c"),
+        Some((255, 255, 221))
+    );
+    assert_eq!(
+        parse_osc11_response("\x1b]11;rgb:1c/1c/1c\x1b\\"),
+        Some((28, 28, 28))
+    );
+    assert_eq!(
+        parse_osc11_response("\x1b]11;rgb:f/8/0"),
+        Some((255, 136, 0))
+    );
+    // Only the device attributes reply: no OSC 11 support.
+    assert_eq!(parse_osc11_response("\x1b[?1;2c"), None);
+    assert_eq!(parse_osc11_response("\x1b]11;rgb:ffff/ffff\x07"), None);
+}
+
+#[test]
+fn test_stat_field() {
+    let stat =
+        "4242 (my (odd) cmd) S 1 4200 4100 34816 4242 4194560 0 0 0 0 0 0 0 0 20 0 1 0 987654";
+    assert_eq!(stat_field(stat, 1), None);
+    assert_eq!(stat_field(stat, 3), Some("S"));
+    assert_eq!(stat_field(stat, 6), Some("4100"));
+    assert_eq!(stat_field(stat, 22), Some("987654"));
+}
+
+#[test]
+fn test_color_depth_from_env() {
+    use ColorDepth::*;
+    assert_eq!(
+        color_depth_from_env(Some("truecolor"), Some("xterm")),
+        Truecolor
+    );
+    assert_eq!(color_depth_from_env(Some("24bit"), None), Truecolor);
+    assert_eq!(color_depth_from_env(None, Some("xterm-direct")), Truecolor);
+    assert_eq!(color_depth_from_env(None, Some("screen-256color")), Ansi256);
+    assert_eq!(
+        color_depth_from_env(Some(""), Some("tmux-256color")),
+        Ansi256
+    );
+    assert_eq!(color_depth_from_env(None, Some("screen")), Ansi16);
+    assert_eq!(color_depth_from_env(None, Some("linux")), Ansi16);
+    assert_eq!(color_depth_from_env(None, Some("vt220")), Ansi16);
+    // Unknown terminals keep 24-bit color: most support it and only lose
+    // COLORTERM over ssh or sudo.
+    for term in [
+        "xterm",
+        "xterm-kitty",
+        "alacritty",
+        "foot",
+        "wezterm",
+        "xterm-ghostty",
+    ] {
+        assert_eq!(
+            color_depth_from_env(None, Some(term)),
+            Truecolor,
+            "{}",
+            term
+        );
+    }
+    assert_eq!(color_depth_from_env(None, None), Truecolor);
+}
+
+#[test]
+fn test_quantize_colors() {
+    assert_eq!(nearest_ansi256((255, 140, 0)), 208);
+    assert_eq!(nearest_ansi256((0, 0, 0)), 16);
+    assert_eq!(nearest_ansi256((255, 255, 255)), 231);
+    assert_eq!(nearest_ansi256((128, 128, 128)), 244);
+    assert_eq!(nearest_ansi256((95, 135, 175)), 67);
+
+    assert_eq!(nearest_ansi16((0, 0, 0)), 0);
+    assert_eq!(nearest_ansi16((255, 140, 0)), 3);
+    assert_eq!(nearest_ansi16((255, 80, 80)), 9);
+    assert_eq!(nearest_ansi16((250, 248, 230)), 15);
+
+    use nu_ansi_term::Color;
+    let orange = (255, 140, 0);
+    assert_eq!(
+        quantize(orange, ColorDepth::Truecolor),
+        Color::Rgb(255, 140, 0)
+    );
+    assert_eq!(quantize(orange, ColorDepth::Ansi256), Color::Fixed(208));
+    assert_eq!(quantize(orange, ColorDepth::Ansi16), Color::Yellow);
+    assert_eq!(quantize((0, 0, 238), ColorDepth::Ansi16), Color::Blue);
+}
+
+#[test]
+fn test_wcag_contrast() {
+    assert_eq!(relative_luminance((0, 0, 0)), 0.0);
+    assert!((relative_luminance((255, 255, 255)) - 1.0).abs() < 1e-9);
+    assert!((contrast_ratio((0, 0, 0), (255, 255, 255)) - 21.0).abs() < 1e-9);
+    assert!((contrast_ratio((118, 118, 118), (255, 255, 255)) - 4.54).abs() < 0.01);
+
+    for rgb in [
+        (255, 149, 0),
+        (38, 139, 210),
+        (10, 200, 90),
+        (128, 128, 128),
+    ] {
+        let (h, s, l) = rgb_to_hsl(rgb);
+        let back = crate::colors::hsl_to_rgb(h, s, l);
+        for (a, b) in [(rgb.0, back.0), (rgb.1, back.1), (rgb.2, back.2)] {
+            assert!(a.abs_diff(b) <= 1, "{:?} -> {:?}", rgb, back);
+        }
+    }
+}
+
+#[test]
+fn test_ensure_contrast_preserves_hue() {
+    let black = (0, 0, 0);
+    // Already readable: untouched.
+    assert_eq!(
+        ensure_contrast((255, 255, 255), black, 4.5),
+        (255, 255, 255)
+    );
+
+    let navy = (0, 0, 128);
+    let fixed = ensure_contrast(navy, black, 3.0);
+    assert!(contrast_ratio(fixed, black) >= 3.0);
+    assert!((rgb_to_hsl(fixed).0 - 240.0).abs() < 1.0, "{:?}", fixed);
+    // The smallest change: just past the threshold, not all the way to white.
+    assert!(contrast_ratio(fixed, black) < 3.2, "{:?}", fixed);
+
+    let orange = (255, 149, 0);
+    let white = (255, 255, 255);
+    let fixed = ensure_contrast(orange, white, 4.5);
+    assert!(contrast_ratio(fixed, white) >= 4.5);
+    assert!((rgb_to_hsl(fixed).0 - rgb_to_hsl(orange).0).abs() < 1.5);
+
+    // Unreachable on mid gray: the end with the most contrast.
+    assert_eq!(
+        ensure_contrast((120, 120, 120), (128, 128, 128), 21.0),
+        black
+    );
+}
+
+#[test]
+fn test_adjust_colors_against_real_background() {
+    let cfg = parse_config(
+        r##"
+theme = "fox-dark"
+[colors]
+cwd = [40, 40, 40]
+"##,
+        None,
+    )
+    .unwrap();
+    let colors = cfg.colors.clone().unwrap();
+    let ratio = |spec: &ColorSpec, bg| contrast_ratio(parse_color(spec).unwrap(), bg);
+
+    // Dark terminal: the dark cwd is lightened, fine colors keep their spec.
+    let dark = adjust_colors_for_background(colors.clone(), &cfg, false);
+    assert!(ratio(&dark.cwd, (0, 0, 0)) >= 3.0);
+    assert_eq!(format!("{:?}", dark.model), format!("{:?}", colors.model));
+
+    // Light terminal: the orange model is darkened.
+    let light = adjust_colors_for_background(colors.clone(), &cfg, true);
+    assert!(ratio(&light.model, (255, 255, 255)) >= 3.0);
+    assert!(ratio(&colors.model, (255, 255, 255)) < 3.0);
+
+    // With enable_background, contrast is against colors.background.
+    let mut painted = cfg.clone();
+    painted.enable_background = true;
+    painted.min_contrast = 4.5;
+    let adjusted = adjust_colors_for_background(colors.clone(), &painted, true);
+    let bg = parse_color(&colors.background).unwrap();
+    assert!(ratio(&adjusted.cwd, bg) >= 4.5);
+    assert!(ratio(&adjusted.version, bg) >= 4.5);
+
+    // Powerline segments use their own background.
+    let mut powerline = cfg.clone();
+    powerline.segment_style = SegmentStyle::Powerline;
+    let mut segmented = colors.clone();
+    segmented
+        .backgrounds
+        .insert("model".to_string(), ColorSpec::Rgb(vec![255, 200, 120]));
+    let adjusted = adjust_colors_for_background(segmented, &powerline, false);
+    assert!(ratio(&adjusted.model, (255, 200, 120)) >= 3.0);
+
+    // The default palette is checked too: white on the default orange
+    // background is too faint.
+    let default_bg = parse_color(&Colors::default().background).unwrap();
+    let defaults = crate::colors::get_colors(None, false);
+    assert!(ratio(&defaults.model, default_bg) < 3.0);
+    let adjusted = adjust_colors_for_background(defaults.clone(), &painted, false);
+    assert!(ratio(&adjusted.model, default_bg) >= 4.5);
+    let plain = adjust_colors_for_background(defaults.clone(), &Config::default(), false);
+    assert_eq!(format!("{:?}", plain), format!("{:?}", defaults));
+
+    // min_contrast = 1 leaves everything alone.
+    let mut off = cfg.clone();
+    off.min_contrast = 1.0;
+    let kept = adjust_colors_for_background(colors.clone(), &off, true);
+    assert_eq!(format!("{:?}", kept), format!("{:?}", colors));
+}
+
+#[test]
+fn test_format_uses() {
+    let uses_in = |fmt: &str, name: &str| template::uses(&parse_format(fmt).unwrap(), name);
+    assert!(uses_in("{stack}", "stack"));
+    assert!(uses_in("{stack?none}", "stack"));
+    assert!(uses_in("{ stack }", "stack"));
+    assert!(uses_in("{model} {[?stack>1 deep]}", "stack"));
+    assert!(uses_in("{[ ({stack})]}", "stack"));
+    assert!(uses_in("{[?untracked {gitdelta}]}", "untracked"));
+    assert!(!uses_in("{model} | {cwd}", "stack"));
+    assert!(!uses_in("stack {{stack}}", "stack"));
+}
@@ -0,0 +1,64 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml::{Table, Value};
+
+/// Palettes shipped with foxtail, selected with `theme = "<name>"`.
+pub const THEMES: [(&str, &str); 5] = [
+    ("fox-dark", include_str!("themes/fox-dark.toml")),
+    ("fox-light", include_str!("themes/fox-light.toml")),
+    ("solarized-dark", include_str!("themes/solarized-dark.toml")),
+    (
+        "solarized-light",
+        include_str!("themes/solarized-light.toml"),
+    ),
+    ("nord", include_str!("themes/nord.toml")),
+];
+
+/// Loads the colors of a bundled theme, or of a TOML file with the same keys
+/// as `[colors]`. Relative paths are resolved against `base_dir`, the
+/// directory of the config file.
+pub fn load_theme(theme: &str, base_dir: Option<&Path>) -> Result<Table, String> {
+    if let Some((_, contents)) = THEMES.iter().find(|(name, _)| *name == theme) {
+        return contents
+            .parse()
+            .map_err(|e| format!("theme {}: {}", theme, e));
+    }
+    let path = theme_path(theme, base_dir);
+    let contents = fs::read_to_string(&path).map_err(|e| {
+        let names: Vec<&str> = THEMES.iter().map(|(name, _)| *name).collect();
+        format!(
+            "{}: {} (bundled themes: {})",
+            path.display(),
+            e,
+            names.join(", ")
+        )
+    })?;
+    contents
+        .parse()
+        .map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+fn theme_path(theme: &str, base_dir: Option<&Path>) -> PathBuf {
+    if let Some(rest) = theme.strip_prefix("~/") {
+        if let Some(home) = dirs::home_dir() {
+            return home.join(rest);
+        }
+    }
+    match base_dir {
+        Some(dir) => dir.join(theme),
+        None => PathBuf::from(theme),
+    }
+}
+
+/// Layers user `[colors]` entries over a theme. `backgrounds` is merged per
+/// segment rather than replaced as a whole.
+pub fn merge_colors(theme: &mut Table, overrides: Table) {
+    for (key, value) in overrides {
+        match (theme.get_mut(&key), value) {
+            (Some(Value::Table(base)), Value::Table(table)) => base.extend(table),
+            (_, value) => {
+                theme.insert(key, value);
+            }
+        }
+    }
+}
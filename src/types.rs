@@ -1,41 +1,74 @@
 use serde::Deserialize;
+use serde_json::Value;
 
-#[derive(Debug, Deserialize)]
+/// Upper bound on the number of malformed fields dropped before giving up.
+const MAX_PRUNED_FIELDS: usize = 32;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
 pub struct StatusInput {
+    #[allow(dead_code)]
+    pub hook_event_name: Option<String>,
+    pub session_id: Option<String>,
+    #[allow(dead_code)]
+    pub transcript_path: Option<String>,
     pub cwd: String,
     pub model: Model,
     pub workspace: Workspace,
+    pub version: Option<String>,
+    pub output_style: Option<OutputStyle>,
     pub cost: Cost,
     pub context_window: ContextWindow,
+    #[allow(dead_code)]
+    pub exceeds_200k_tokens: Option<bool>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
 pub struct Model {
+    #[allow(dead_code)]
+    pub id: Option<String>,
     pub display_name: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
 pub struct Workspace {
     pub current_dir: String,
+    pub project_dir: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct OutputStyle {
+    pub name: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
 pub struct Cost {
     pub total_cost_usd: f64,
     pub total_duration_ms: u64,
+    pub total_api_duration_ms: Option<u64>,
     pub total_lines_added: u32,
     pub total_lines_removed: u32,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
 pub struct ContextWindow {
+    #[allow(dead_code)]
+    pub total_input_tokens: Option<u64>,
+    #[allow(dead_code)]
+    pub total_output_tokens: Option<u64>,
     pub context_window_size: u32,
     pub used_percentage: f64,
     pub remaining_percentage: f64,
     pub current_usage: Option<CurrentUsage>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
 pub struct CurrentUsage {
     #[allow(dead_code)]
     pub input_tokens: u32,
@@ -44,3 +77,48 @@ pub struct CurrentUsage {
     pub cache_creation_input_tokens: u32,
     pub cache_read_input_tokens: u32,
 }
+
+/// Parses the JSON sent by Claude Code without ever failing.
+///
+/// Every field is optional and unknown fields are ignored, so only malformed
+/// values can make the strict parse fail. In that case the offending fields are
+/// dropped one by one and whatever remains is returned, along with the original
+/// error so the caller can flag the status line as degraded.
+pub fn parse_status_input(input: &str) -> (StatusInput, Option<String>) {
+    let err = match serde_json::from_str::<StatusInput>(input) {
+        Ok(d) => return (d, None),
+        Err(e) => e.to_string(),
+    };
+    let mut value = match serde_json::from_str::<Value>(input) {
+        Ok(v @ Value::Object(_)) => v,
+        _ => return (StatusInput::default(), Some(err)),
+    };
+    for _ in 0..MAX_PRUNED_FIELDS {
+        match serde_path_to_error::deserialize::<_, StatusInput>(value.clone()) {
+            Ok(d) => return (d, Some(err)),
+            Err(e) => {
+                let path = e.path().to_string();
+                if !remove_path(&mut value, &path) {
+                    break;
+                }
+            }
+        }
+    }
+    (StatusInput::default(), Some(err))
+}
+
+fn remove_path(value: &mut Value, path: &str) -> bool {
+    let mut keys: Vec<&str> = path.split('.').collect();
+    let last = match keys.pop() {
+        Some(k) if !k.is_empty() && k != "?" => k,
+        _ => return false,
+    };
+    let mut cur = value;
+    for key in keys {
+        cur = match cur.get_mut(key) {
+            Some(v) => v,
+            None => return false,
+        };
+    }
+    cur.as_object_mut().and_then(|o| o.remove(last)).is_some()
+}